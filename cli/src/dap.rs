@@ -0,0 +1,588 @@
+//! A debug adapter for editors that speak the debug adapter protocol, like VS Code.
+//!
+//! The adapter communicates over stdin and stdout or a local TCP socket. It supports launching
+//! source files and program images, source breakpoints, stepping, continue and pause, and shows
+//! the registers as variables and the RAM and the data memory as memory.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+use serde_json::{json, Value};
+
+use kasm::{cpu::{self, CPU, ExecResult}, Result, URS};
+use kasm::lexer::options::Options;
+use kasm::source::Location;
+use kasm::source_map::SourceMap;
+
+use crate::rpc;
+
+/// The instructions executed between checking for new requests, like `pause`.
+const STEPS_BETWEEN_REQUESTS: usize = 10_000;
+
+/// The only thread, the CPU has no concurrency.
+const THREAD_ID: u64 = 1;
+
+const REGISTERS_REFERENCE: u64 = 1;
+const DATA_REGISTERS_REFERENCE: u64 = 2;
+const STACK_REFERENCE: u64 = 3;
+const RAM_MEMORY_REFERENCE: &str = "ram";
+const DATA_MEMORY_REFERENCE: &str = "memory";
+
+/// Runs the debug adapter till the client disconnects.
+///
+/// Listens on `port` on localhost for a single client if given, and uses stdin and stdout otherwise.
+pub fn run(port: Option<u16>, options: &Options, config: &cpu::Config) -> Result<()> {
+    match port {
+        Some(port) => {
+            let listener = TcpListener::bind(("127.0.0.1", port))?;
+            eprintln!("Waiting for a debugger on port {}", port);
+            let (stream, _) = listener.accept()?;
+            let input = BufReader::new(stream.try_clone()?);
+            Adapter::new(input, stream, options.clone(), config.clone()).run()
+        }
+        None => Adapter::new(BufReader::new(std::io::stdin()), std::io::stdout(), options.clone(), config.clone()).run(),
+    }
+}
+
+/// A loaded program.
+struct Program {
+    cpu: CPU<std::io::Sink>,
+    source_map: SourceMap,
+    /// The main source file, included files are named by the source map.
+    path: PathBuf,
+}
+
+pub struct Adapter<W: Write> {
+    requests: Receiver<Value>,
+    output: W,
+    options: Options,
+    config: cpu::Config,
+    seq: u64,
+
+    program: Option<Program>,
+    stop_on_entry: bool,
+    configured: bool,
+    started: bool,
+    running: bool,
+    /// Whether the program was resumed and did not execute an instruction yet,
+    /// so a breakpoint at the current address is left instead of hit again.
+    resumed: bool,
+    disconnected: bool,
+    /// The lines of all breakpoints by the path of their source file.
+    breakpoints: HashMap<PathBuf, Vec<usize>>,
+    /// The addresses of all breakpoints of the loaded program.
+    addresses: BTreeSet<URS>,
+}
+
+impl<W: Write> Adapter<W> {
+    pub fn new<R: BufRead + Send + 'static>(mut input: R, output: W, options: Options, config: cpu::Config) -> Self {
+        // requests are read on their own thread, to notice `pause` while the program runs
+        let (sender, requests) = mpsc::channel();
+        std::thread::spawn(move || {
+            while let Ok(Some(message)) = rpc::read_message(&mut input) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            requests,
+            output,
+            options,
+            config,
+            seq: 0,
+            program: None,
+            stop_on_entry: false,
+            configured: false,
+            started: false,
+            running: false,
+            resumed: false,
+            disconnected: false,
+            breakpoints: HashMap::new(),
+            addresses: BTreeSet::new(),
+        }
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        while !self.disconnected {
+            let request = if self.running {
+                match self.requests.try_recv() {
+                    Ok(request) => Some(request),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match self.requests.recv() {
+                    Ok(request) => Some(request),
+                    Err(_) => return Ok(()),
+                }
+            };
+
+            if let Some(request) = request {
+                if !self.handle_request(&request)? {
+                    return Ok(());
+                }
+            }
+            if self.running {
+                self.execute(STEPS_BETWEEN_REQUESTS, |_, _| false)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a request, and returns whether the session continues.
+    fn handle_request(&mut self, request: &Value) -> Result<bool> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let arguments = &request["arguments"];
+
+        let body = match command {
+            "initialize" => {
+                self.respond(request, Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsReadMemoryRequest": true,
+                    "supportsTerminateRequest": true,
+                })))?;
+                self.event("initialized", Value::Null)?;
+                return Ok(true);
+            }
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => Ok(self.set_breakpoints(arguments)),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "configurationDone" => {
+                self.configured = true;
+                self.respond(request, Ok(Value::Null))?;
+                return self.start().map(|_| true);
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(json!({
+                "scopes": [
+                    { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+                    { "name": "Data registers", "variablesReference": DATA_REGISTERS_REFERENCE, "expensive": false },
+                    { "name": "Stack", "variablesReference": STACK_REFERENCE, "expensive": false },
+                ]
+            })),
+            "variables" => Ok(self.variables(arguments)),
+            "readMemory" => self.read_memory(arguments),
+            "continue" => {
+                self.respond(request, Ok(json!({ "allThreadsContinued": true })))?;
+                self.running = self.program.is_some();
+                self.resumed = true;
+                return Ok(true);
+            }
+            "next" | "stepIn" | "stepOut" => {
+                self.respond(request, Ok(Value::Null))?;
+                let step = match command {
+                    _ if arguments["granularity"].as_str() == Some("instruction") => Step::Instruction,
+                    "stepIn" => Step::In,
+                    "stepOut" => Step::Out,
+                    _ => Step::Over,
+                };
+                self.step(step)?;
+                return Ok(true);
+            }
+            "pause" => {
+                self.respond(request, Ok(Value::Null))?;
+                if self.running {
+                    self.running = false;
+                    self.stopped("pause", None)?;
+                }
+                return Ok(true);
+            }
+            "disconnect" | "terminate" => {
+                self.respond(request, Ok(Value::Null))?;
+                self.event("terminated", Value::Null)?;
+                return Ok(command == "terminate");
+            }
+            command => Err(format!("The request {} is not supported", command)),
+        };
+
+        self.respond(request, body)?;
+        Ok(true)
+    }
+
+    fn launch(&mut self, arguments: &Value) -> std::result::Result<Value, String> {
+        let path = arguments["program"]
+            .as_str()
+            .map(PathBuf::from)
+            .ok_or_else(|| "Expected the path of the program to debug in `program`".to_owned())?;
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+
+        let image = crate::load_image(&path, &self.options).map_err(|err| err.to_string())?;
+        let mut cpu = CPU::new(image.ram, self.config.clone(), std::io::sink());
+        *cpu.BZ_mut() = image.entry;
+
+        self.program = Some(Program {
+            cpu,
+            source_map: image.source_map.unwrap_or_default(),
+            path,
+        });
+        self.resolve_breakpoints();
+        self.start().map_err(|err| err.to_string())?;
+        Ok(Value::Null)
+    }
+
+    /// Starts the program once it is loaded and the client sent all breakpoints.
+    fn start(&mut self) -> Result<()> {
+        if self.started || !self.configured || self.program.is_none() {
+            return Ok(());
+        }
+
+        self.started = true;
+        if self.stop_on_entry {
+            self.stopped("entry", None)
+        } else {
+            self.running = true;
+            Ok(())
+        }
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Value {
+        let path = PathBuf::from(arguments["source"]["path"].as_str().unwrap_or_default());
+        let lines = arguments["breakpoints"]
+            .as_array()
+            .map(|breakpoints| breakpoints
+                .iter()
+                .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                .map(|line| line as usize)
+                .collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        self.breakpoints.insert(path.clone(), lines.clone());
+        self.resolve_breakpoints();
+
+        let breakpoints = lines
+            .into_iter()
+            .map(|line| match self.breakpoint_address(&path, line) {
+                Some((_, line)) => json!({ "verified": true, "line": line }),
+                None if self.program.as_ref().is_some_and(|program| program.file(&path).is_none()) => {
+                    json!({ "verified": false, "line": line, "message": "The file is not part of the program" })
+                }
+                None => json!({ "verified": false, "line": line, "message": "There is no code in or after this line" }),
+            })
+            .collect::<Vec<_>>();
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn resolve_breakpoints(&mut self) {
+        self.addresses = self.breakpoints
+            .iter()
+            .flat_map(|(path, lines)| lines.iter().map(move |&line| (path, line)))
+            .filter_map(|(path, line)| self.breakpoint_address(path, line))
+            .map(|(address, _)| address)
+            .collect();
+    }
+
+    /// The first address of a line, or of the next line with code, together with that line.
+    fn breakpoint_address(&self, path: &Path, line: usize) -> Option<(URS, usize)> {
+        let program = self.program.as_ref()?;
+        let file = program.file(path)?;
+
+        program.source_map
+            .lines()
+            .iter()
+            .enumerate()
+            .filter(|(_, location)| location.file == file && location.line >= line)
+            .min_by_key(|&(address, location)| (location.line, address))
+            .map(|(address, location)| (address as URS, location.line))
+    }
+
+    /// Executes up to `steps` instructions, till a breakpoint is hit,
+    /// the program ends, or `stop` returns true.
+    fn execute<F: Fn(&Program, Option<&Location>) -> bool>(&mut self, steps: usize, stop: F) -> Result<()> {
+        let start = self.program
+            .as_ref()
+            .and_then(|program| program.source_map.line(program.cpu.BZ()).cloned());
+
+        for _ in 0..steps {
+            let program = match self.program {
+                Some(ref mut program) => program,
+                None => return Ok(()),
+            };
+            if !self.resumed && self.addresses.contains(&program.cpu.BZ()) {
+                self.running = false;
+                return self.stopped("breakpoint", None);
+            }
+            self.resumed = false;
+
+            match program.cpu.step() {
+                Ok(ExecResult::Print(text)) => self.output("stdout", &format!("{}\n", text))?,
+                Ok(ExecResult::Ended) => {
+                    self.running = false;
+                    self.program = None;
+                    self.event("exited", json!({ "exitCode": 0 }))?;
+                    return self.event("terminated", Value::Null);
+                }
+                Ok(ExecResult::HitBreakPoint) => {
+                    self.running = false;
+                    return self.stopped("breakpoint", None);
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    let err = program.source_map.annotate(err);
+                    self.running = false;
+                    self.output("stderr", &format!("{}\n", err))?;
+                    return self.stopped("exception", Some(err.summary()));
+                }
+            }
+
+            let program = self.program.as_ref().expect("The program only ends above");
+            if stop(program, start.as_ref()) {
+                self.running = false;
+                return self.stopped("step", None);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Executes instructions till the step is done.
+    fn step(&mut self, step: Step) -> Result<()> {
+        let depth = match self.program {
+            Some(ref program) => program.cpu.return_addresses().count(),
+            None => return Ok(()),
+        };
+
+        self.running = true;
+        self.resumed = true;
+        while self.running {
+            self.execute(STEPS_BETWEEN_REQUESTS, |program, start| {
+                let line_changed = program.source_map.line(program.cpu.BZ()) != start;
+                let current_depth = program.cpu.return_addresses().count();
+                match step {
+                    Step::Instruction => true,
+                    Step::In => line_changed,
+                    Step::Out if depth > 0 => current_depth < depth,
+                    Step::Over | Step::Out => line_changed && current_depth <= depth,
+                }
+            })?;
+
+            // a step may take long, like a jump to the same line
+            if let Ok(request) = self.requests.try_recv() {
+                if !self.handle_request(&request)? {
+                    self.running = false;
+                    self.disconnected = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn stack_trace(&self) -> Value {
+        let program = match self.program {
+            Some(ref program) => program,
+            None => return json!({ "stackFrames": [], "totalFrames": 0 }),
+        };
+
+        // every active call adds a frame at its `CALL` instruction
+        let mut addresses = program.cpu
+            .return_addresses()
+            .map(|addr| addr.saturating_sub(1))
+            .collect::<Vec<_>>();
+        addresses.push(program.cpu.BZ());
+
+        let frames = addresses
+            .into_iter()
+            .rev()
+            .enumerate()
+            .map(|(id, addr)| program.frame(id, addr))
+            .collect::<Vec<_>>();
+        json!({ "totalFrames": frames.len(), "stackFrames": frames })
+    }
+
+    fn variables(&self, arguments: &Value) -> Value {
+        let cpu = match self.program {
+            Some(ref program) => &program.cpu,
+            None => return json!({ "variables": [] }),
+        };
+        let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+
+        let variables = match arguments["variablesReference"].as_u64() {
+            Some(REGISTERS_REFERENCE) => vec![
+                variable("A".to_owned(), cpu.A().to_string()),
+                variable("BZ".to_owned(), cpu.BZ().to_string()),
+                variable("Flags".to_owned(), cpu.flags().to_string()),
+                json!({
+                    "name": "RAM",
+                    "value": format!("{} instructions", cpu.ram().len()),
+                    "variablesReference": 0,
+                    "memoryReference": RAM_MEMORY_REFERENCE,
+                }),
+                json!({
+                    "name": "Data memory",
+                    "value": format!("{} words", cpu.memory().len()),
+                    "variablesReference": 0,
+                    "memoryReference": DATA_MEMORY_REFERENCE,
+                }),
+            ],
+            Some(DATA_REGISTERS_REFERENCE) => cpu.Rx()
+                .iter()
+                .enumerate()
+                .map(|(i, val)| variable(format!("Rx[{}]", i), val.to_string()))
+                .collect(),
+            // the top of the stack first
+            Some(STACK_REFERENCE) => cpu.stack()
+                .iter()
+                .enumerate()
+                .rev()
+                .map(|(i, val)| variable(format!("[{}]", i), val.to_string()))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        json!({ "variables": variables })
+    }
+
+    /// Reads the RAM, where every instruction takes 16 bytes: the instruction code
+    /// followed by the argument, both in little endian.
+    /// Every word of the data memory takes 8 bytes, in little endian as well.
+    fn read_memory(&self, arguments: &Value) -> std::result::Result<Value, String> {
+        let program = self.program.as_ref().ok_or_else(|| "No program is loaded".to_owned())?;
+
+        let bytes = match arguments["memoryReference"].as_str() {
+            Some(RAM_MEMORY_REFERENCE) => program.cpu
+                .ram()
+                .iter()
+                .flat_map(|&(inst, val)| IntoIterator::into_iter(inst.to_le_bytes()).chain(val.to_le_bytes()))
+                .collect::<Vec<_>>(),
+            Some(DATA_MEMORY_REFERENCE) => program.cpu
+                .memory()
+                .iter()
+                .flat_map(|word| IntoIterator::into_iter(word.to_le_bytes()))
+                .collect(),
+            _ => return Err("Only the RAM and the data memory can be read".to_owned()),
+        };
+        let offset = arguments["offset"].as_i64().unwrap_or(0).max(0) as usize;
+        let count = arguments["count"].as_u64().unwrap_or(0) as usize;
+        let start = offset.min(bytes.len());
+        let end = offset.saturating_add(count).min(bytes.len());
+
+        Ok(json!({
+            "address": format!("{:#x}", start),
+            "data": base64(&bytes[start..end]),
+            "unreadableBytes": count - (end - start),
+        }))
+    }
+
+    fn respond(&mut self, request: &Value, body: std::result::Result<Value, String>) -> Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": body.is_ok(),
+        });
+        match body {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message)
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) -> Result<()> {
+        self.event("stopped", json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+            "text": text,
+        }))
+    }
+
+    fn output(&mut self, category: &str, output: &str) -> Result<()> {
+        self.event("output", json!({ "category": category, "output": output }))
+    }
+
+    fn send(&mut self, mut message: Value) -> Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        Ok(rpc::write_message(&mut self.output, &message)?)
+    }
+}
+
+/// How far `next`, `stepIn` and `stepOut` execute.
+#[derive(Clone, Copy)]
+enum Step {
+    Instruction,
+    /// Till the source line changes, also into called subroutines.
+    In,
+    /// Till the source line changes, without stopping in called subroutines.
+    Over,
+    /// Till the current subroutine returns.
+    Out,
+}
+
+impl Program {
+    /// A stack frame at an address, named after the jump point it follows.
+    fn frame(&self, id: usize, addr: URS) -> Value {
+        let location = self.source_map.line(addr);
+        let name = self.source_map
+            .all_labels()
+            .take_while(|&(label, _)| label <= addr)
+            .last()
+            .map(|(_, name)| format!(".{}", name))
+            .unwrap_or_else(|| "main".to_owned());
+
+        let mut frame = json!({
+            "id": id,
+            "name": name,
+            "line": location.map(|location| location.line).unwrap_or(0),
+            "column": 1,
+            "instructionPointerReference": addr.to_string(),
+        });
+        if let Some(location) = location {
+            let path = match location.file {
+                Some(ref file) => PathBuf::from(file.as_ref()),
+                None => self.path.clone(),
+            };
+            frame["source"] = json!({ "path": path.display().to_string() });
+        }
+        frame
+    }
+
+    /// The name of a source file in the source map, `Some(None)` for the main file,
+    /// and `None` for files that are not part of the program.
+    fn file(&self, path: &Path) -> Option<Option<std::sync::Arc<str>>> {
+        if path == self.path {
+            return Some(None);
+        }
+
+        self.source_map
+            .lines()
+            .iter()
+            .filter_map(|location| location.file.as_ref())
+            .find(|file| Path::new(file.as_ref()) == path)
+            .cloned()
+            .map(Some)
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &byte)| n | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
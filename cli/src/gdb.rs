@@ -0,0 +1,488 @@
+//! A stub for the GDB remote serial protocol, to debug programs with GDB and its front ends.
+//!
+//! The registers are `A`, `BZ`, `Rx[0]` to `Rx[15]` and the flags, each 64 bits wide.
+//! GDB addresses memory in bytes, so every instruction of the RAM takes 16 bytes: the instruction
+//! code followed by the argument, both in little endian. `BZ` is shown as the address of the next
+//! instruction in these bytes, which is `BZ * 16`, so breakpoints and `$pc` agree with the memory.
+
+use std::collections::BTreeSet;
+use std::convert::{TryFrom, TryInto};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+use kasm::{cpu::{self, CPU, ExecResult}, DATA_REGISTERS, IRS, Result, URS};
+use kasm::lexer::options::Options;
+use kasm::source_map::SourceMap;
+
+/// The number of registers: `A`, `BZ`, `Rx` and the flags.
+const REGISTERS: usize = 3 + DATA_REGISTERS;
+
+/// The bytes an instruction takes in memory.
+const WORD_SIZE: URS = 16;
+
+/// The instructions executed between checking whether GDB interrupted the program.
+const STEPS_BETWEEN_INTERRUPTS: usize = 10_000;
+
+/// The byte GDB sends to interrupt a running program, outside of any packet.
+const INTERRUPT: u8 = 0x03;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.kasm.cpu">
+    <flags id="kasm_flags" size="8">
+      <field name="C" start="0" end="0"/>
+      <field name="V" start="1" end="1"/>
+      <field name="Z" start="2" end="2"/>
+      <field name="N" start="3" end="3"/>
+    </flags>
+    <reg name="A" bitsize="64" type="int64" regnum="0"/>
+    <reg name="BZ" bitsize="64" type="code_ptr" regnum="1"/>
+    <reg name="R0" bitsize="64" type="int64"/>
+    <reg name="R1" bitsize="64" type="int64"/>
+    <reg name="R2" bitsize="64" type="int64"/>
+    <reg name="R3" bitsize="64" type="int64"/>
+    <reg name="R4" bitsize="64" type="int64"/>
+    <reg name="R5" bitsize="64" type="int64"/>
+    <reg name="R6" bitsize="64" type="int64"/>
+    <reg name="R7" bitsize="64" type="int64"/>
+    <reg name="R8" bitsize="64" type="int64"/>
+    <reg name="R9" bitsize="64" type="int64"/>
+    <reg name="R10" bitsize="64" type="int64"/>
+    <reg name="R11" bitsize="64" type="int64"/>
+    <reg name="R12" bitsize="64" type="int64"/>
+    <reg name="R13" bitsize="64" type="int64"/>
+    <reg name="R14" bitsize="64" type="int64"/>
+    <reg name="R15" bitsize="64" type="int64"/>
+    <reg name="flags" bitsize="64" type="kasm_flags"/>
+  </feature>
+</target>
+"#;
+
+/// Loads a program and waits for GDB on `port` on localhost, till GDB detaches or kills the program.
+pub fn run(file: &Path, port: u16, options: &Options, config: &cpu::Config) -> Result<()> {
+    let image = crate::load_image(file, options)?;
+    let mut cpu = CPU::new(image.ram, config.clone(), std::io::sink());
+    *cpu.BZ_mut() = image.entry;
+
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("Waiting for GDB on port {}, connect with `target remote :{}`", port, port);
+    let (stream, _) = listener.accept()?;
+    let input = BufReader::new(stream.try_clone()?);
+
+    Stub::new(input, stream, cpu, image.source_map.unwrap_or_default()).run()
+}
+
+/// What GDB sent.
+enum Input {
+    /// A packet, or `None` if its checksum didn't match.
+    Packet(Option<Vec<u8>>),
+    Interrupt,
+}
+
+/// Why the program stopped.
+enum Stop {
+    Signal(u8),
+    Exited,
+}
+
+pub struct Stub<W: Write> {
+    input: Receiver<Input>,
+    output: W,
+    cpu: CPU<std::io::Sink>,
+    source_map: SourceMap,
+    /// The addresses of all software breakpoints, counted in instructions.
+    breakpoints: BTreeSet<URS>,
+    ack: bool,
+}
+
+impl<W: Write> Stub<W> {
+    pub fn new<R: BufRead + Send + 'static>(mut input: R, output: W, cpu: CPU<std::io::Sink>, source_map: SourceMap) -> Self {
+        // GDB is read on its own thread, to notice interrupts while the program runs
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            while let Ok(Some(input)) = read_input(&mut input) {
+                if sender.send(input).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            input: receiver,
+            output,
+            cpu,
+            source_map,
+            breakpoints: BTreeSet::new(),
+            ack: true,
+        }
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        while let Ok(input) = self.input.recv() {
+            let packet = match input {
+                Input::Packet(Some(packet)) => packet,
+                Input::Packet(None) => {
+                    self.output.write_all(b"-")?;
+                    self.output.flush()?;
+                    continue;
+                }
+                Input::Interrupt => continue,
+            };
+            if self.ack {
+                self.output.write_all(b"+")?;
+            }
+
+            let packet = String::from_utf8_lossy(&packet).into_owned();
+            match self.handle_packet(&packet)? {
+                Some(response) => self.send(&response)?,
+                None => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a packet and returns the response, or `None` if GDB detached.
+    fn handle_packet(&mut self, packet: &str) -> Result<Option<String>> {
+        let (command, arguments) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+
+        let response = match command {
+            "?" => stop_reply(Stop::Signal(SIGTRAP)),
+            "g" => self.registers().iter().map(|&value| encode_register(value)).collect(),
+            "G" => {
+                let values = (0..REGISTERS)
+                    .map(|i| arguments.get(i * 16..(i + 1) * 16).and_then(decode_register))
+                    .collect::<Option<Vec<_>>>();
+                match values {
+                    Some(values) => respond(values
+                        .into_iter()
+                        .enumerate()
+                        .try_for_each(|(i, value)| self.set_register(i, value))),
+                    None => error(),
+                }
+            }
+            "p" => match usize::from_str_radix(arguments, 16).ok().and_then(|i| self.registers().get(i).copied()) {
+                Some(value) => encode_register(value),
+                None => error(),
+            },
+            "P" => match arguments.split_once('=') {
+                Some((i, value)) => match (usize::from_str_radix(i, 16), decode_register(value)) {
+                    (Ok(i), Some(value)) => respond(self.set_register(i, value)),
+                    _ => error(),
+                },
+                None => error(),
+            },
+            "m" => match parse_range(arguments).and_then(|(addr, len)| self.read_memory(addr, len)) {
+                Some(bytes) => encode_hex(&bytes),
+                None => error(),
+            },
+            "M" => {
+                let write = arguments
+                    .split_once(':')
+                    .and_then(|(range, data)| Some((parse_range(range)?, decode_hex(data)?)))
+                    .filter(|((_, len), data)| *len == data.len() as URS);
+                match write {
+                    Some(((addr, _), data)) => respond(self.write_memory(addr, &data)),
+                    None => error(),
+                }
+            }
+            "Z" | "z" => match self.set_breakpoint(command == "Z", arguments) {
+                Some(Ok(())) => "OK".to_owned(),
+                Some(Err(())) => error(),
+                // only software breakpoints are supported
+                None => String::new(),
+            },
+            "c" => {
+                if !arguments.is_empty() && self.set_address(arguments).is_err() {
+                    return Ok(Some(error()));
+                }
+                stop_reply(self.resume(false)?)
+            }
+            "s" => {
+                if !arguments.is_empty() && self.set_address(arguments).is_err() {
+                    return Ok(Some(error()));
+                }
+                stop_reply(self.resume(true)?)
+            }
+            "H" | "T" => "OK".to_owned(),
+            "D" => {
+                self.send("OK")?;
+                return Ok(None);
+            }
+            "k" => return Ok(None),
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        };
+
+        Ok(Some(response))
+    }
+
+    /// Handles general queries and settings.
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            "PacketSize=4000;QStartNoAckMode+;qXfer:features:read+".to_owned()
+        } else if packet == "QStartNoAckMode" {
+            self.ack = false;
+            "OK".to_owned()
+        } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            match parse_range(range) {
+                Some((offset, len)) => {
+                    let start = (offset as usize).min(TARGET_XML.len());
+                    let end = start.saturating_add(len as usize).min(TARGET_XML.len());
+                    let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+                    format!("{}{}", more, &TARGET_XML[start..end])
+                }
+                None => error(),
+            }
+        } else {
+            match packet {
+                "qAttached" => "1".to_owned(),
+                "qC" => "QC1".to_owned(),
+                "qfThreadInfo" => "m1".to_owned(),
+                "qsThreadInfo" => "l".to_owned(),
+                _ => String::new(),
+            }
+        }
+    }
+
+    /// Executes one instruction, or till a breakpoint is hit, the program ends, or GDB interrupts it.
+    fn resume(&mut self, single_step: bool) -> Result<Stop> {
+        let mut first = true;
+        loop {
+            for _ in 0..STEPS_BETWEEN_INTERRUPTS {
+                if !first && self.breakpoints.contains(&self.cpu.BZ()) {
+                    return Ok(Stop::Signal(SIGTRAP));
+                }
+                first = false;
+
+                match self.cpu.step() {
+                    Ok(ExecResult::Print(text)) => self.console(&format!("{}\n", text))?,
+                    Ok(ExecResult::Ended) => return Ok(Stop::Exited),
+                    Ok(ExecResult::HitBreakPoint) => return Ok(Stop::Signal(SIGTRAP)),
+                    Ok(_) => {}
+                    Err(err) => {
+                        let err = self.source_map.annotate(err);
+                        self.console(&format!("{}\n", err))?;
+                        return Ok(Stop::Signal(SIGILL));
+                    }
+                }
+                if single_step {
+                    return Ok(Stop::Signal(SIGTRAP));
+                }
+            }
+
+            loop {
+                match self.input.try_recv() {
+                    Ok(Input::Interrupt) => return Ok(Stop::Signal(SIGINT)),
+                    // GDB only sends packets when the program stopped
+                    Ok(Input::Packet(_)) => {}
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok(Stop::Signal(SIGINT)),
+                }
+            }
+        }
+    }
+
+    fn set_breakpoint(&mut self, insert: bool, arguments: &str) -> Option<std::result::Result<(), ()>> {
+        let mut parts = arguments.split(',');
+        if parts.next() != Some("0") {
+            return None;
+        }
+
+        let address = parts
+            .next()
+            .and_then(|addr| URS::from_str_radix(addr, 16).ok())
+            .filter(|addr| addr.is_multiple_of(WORD_SIZE))
+            .map(|addr| addr / WORD_SIZE);
+        Some(match address {
+            Some(address) if insert => {
+                self.breakpoints.insert(address);
+                Ok(())
+            }
+            Some(address) => {
+                self.breakpoints.remove(&address);
+                Ok(())
+            }
+            None => Err(()),
+        })
+    }
+
+    /// The values of all registers, with `BZ` as the address in bytes.
+    fn registers(&self) -> Vec<u64> {
+        let mut registers = vec![self.cpu.A() as u64, self.cpu.BZ() * WORD_SIZE];
+        registers.extend(self.cpu.Rx().iter().map(|&value| value as u64));
+        registers.push(self.cpu.flags().bits());
+        registers
+    }
+
+    fn set_register(&mut self, i: usize, value: u64) -> std::result::Result<(), ()> {
+        match i {
+            0 => *self.cpu.A_mut() = value as IRS,
+            1 if value.is_multiple_of(WORD_SIZE) => *self.cpu.BZ_mut() = value / WORD_SIZE,
+            i if (2..2 + DATA_REGISTERS).contains(&i) => self.cpu.Rx_mut()[i - 2] = value as IRS,
+            i if i == REGISTERS - 1 => *self.cpu.flags_mut() = cpu::Flags::from_bits(value),
+            _ => return Err(()),
+        }
+        Ok(())
+    }
+
+    fn set_address(&mut self, addr: &str) -> std::result::Result<(), ()> {
+        let addr = URS::from_str_radix(addr, 16).map_err(|_| ())?;
+        self.set_register(1, addr)
+    }
+
+    fn read_memory(&self, addr: URS, len: URS) -> Option<Vec<u8>> {
+        let bytes = ram_bytes(self.cpu.ram());
+        let start = usize::try_from(addr).ok()?;
+        let end = start.checked_add(usize::try_from(len).ok()?)?;
+        bytes.get(start..end).map(<[u8]>::to_vec)
+    }
+
+    fn write_memory(&mut self, addr: URS, data: &[u8]) -> std::result::Result<(), ()> {
+        let mut bytes = ram_bytes(self.cpu.ram());
+        let start = usize::try_from(addr).map_err(|_| ())?;
+        bytes
+            .get_mut(start..start.checked_add(data.len()).ok_or(())?)
+            .ok_or(())?
+            .copy_from_slice(data);
+
+        for (word, bytes) in self.cpu.ram_mut().iter_mut().zip(bytes.chunks_exact(WORD_SIZE as usize)) {
+            let (inst, val) = bytes.split_at(8);
+            *word = (
+                URS::from_le_bytes(inst.try_into().expect("A word has 8 bytes")),
+                IRS::from_le_bytes(val.try_into().expect("A word has 8 bytes")),
+            );
+        }
+        Ok(())
+    }
+
+    /// Shows text in the GDB console.
+    fn console(&mut self, text: &str) -> Result<()> {
+        self.send(&format!("O{}", encode_hex(text.as_bytes())))
+    }
+
+    fn send(&mut self, packet: &str) -> Result<()> {
+        let packet = escape(packet);
+        let checksum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.output, "${}#{:02x}", packet, checksum)?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads the next packet or interrupt, and skips acknowledgements.
+fn read_input(input: &mut impl BufRead) -> std::io::Result<Option<Input>> {
+    let mut byte = [0];
+    loop {
+        if input.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        match byte[0] {
+            INTERRUPT => return Ok(Some(Input::Interrupt)),
+            b'$' => break,
+            _ => {}
+        }
+    }
+
+    let mut packet = Vec::new();
+    if input.read_until(b'#', &mut packet)? == 0 || packet.pop() != Some(b'#') {
+        return Ok(None);
+    }
+    let mut checksum = [0; 2];
+    input.read_exact(&mut checksum)?;
+
+    let valid = std::str::from_utf8(&checksum)
+        .ok()
+        .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+        == Some(packet.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)));
+    Ok(Some(Input::Packet(valid.then(|| unescape(&packet)))))
+}
+
+/// The RAM as GDB sees it.
+fn ram_bytes(ram: &kasm::RAM) -> Vec<u8> {
+    ram
+        .iter()
+        .flat_map(|&(inst, val)| IntoIterator::into_iter(inst.to_le_bytes()).chain(val.to_le_bytes()))
+        .collect()
+}
+
+fn stop_reply(stop: Stop) -> String {
+    match stop {
+        Stop::Signal(signal) => format!("S{:02x}", signal),
+        Stop::Exited => "W00".to_owned(),
+    }
+}
+
+fn respond(result: std::result::Result<(), ()>) -> String {
+    match result {
+        Ok(()) => "OK".to_owned(),
+        Err(()) => error(),
+    }
+}
+
+fn error() -> String {
+    "E01".to_owned()
+}
+
+/// Parses `addr,len` in hex.
+fn parse_range(s: &str) -> Option<(URS, URS)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((URS::from_str_radix(addr, 16).ok()?, URS::from_str_radix(len, 16).ok()?))
+}
+
+/// Registers are sent in the byte order of the target, which is little endian.
+fn encode_register(value: u64) -> String {
+    encode_hex(&value.to_le_bytes())
+}
+
+fn decode_register(s: &str) -> Option<u64> {
+    let bytes = decode_hex(s)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Escapes the characters that have a meaning in the protocol.
+fn escape(packet: &str) -> String {
+    let mut escaped = String::with_capacity(packet.len());
+    for c in packet.chars() {
+        match c {
+            '$' | '#' | '}' | '*' => {
+                escaped.push('}');
+                escaped.push((c as u8 ^ 0x20) as char);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(packet: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(packet.len());
+    let mut bytes = packet.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'}' => unescaped.extend(bytes.next().map(|byte| byte ^ 0x20)),
+            byte => unescaped.push(byte),
+        }
+    }
+    unescaped
+}
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use kasm::diagnostic::{Diagnostic, Diagnostics, Severity, Span};
+use kasm::lexer::{code_line, Document};
+use kasm::lexer::literal;
+use kasm::lexer::options::Options;
+use kasm::source::{FileSystem, SourceProvider};
+
+/// A file of the analysed program.
+pub struct File {
+    pub uri: String,
+    /// The resolved path of an included file, `None` for the main file.
+    pub path: Option<String>,
+    pub text: String,
+}
+
+/// An open document, assembled together with all files it includes.
+pub struct Analysis {
+    pub doc: Option<Document>,
+    pub diagnostics: Diagnostics,
+    /// The main file, followed by all included files.
+    pub files: Vec<File>,
+    provider: FileSystem,
+}
+
+impl Analysis {
+    /// Assembles the open document `uri`. Included files that are open as well
+    /// are taken from the editor instead of the file system.
+    pub fn new(uri: &str, open: &HashMap<String, String>, options: &Options) -> Self {
+        let text = open.get(uri).cloned().unwrap_or_default();
+        let path = uri_to_path(uri);
+
+        let provider = FileSystem::new(path
+            .as_deref()
+            .and_then(Path::parent)
+            .unwrap_or_else(|| Path::new("")));
+        let name = path
+            .as_deref()
+            .and_then(Path::file_name)
+            .map(|name| provider.resolve(&name.to_string_lossy(), None));
+
+        let workspace = Workspace { fs: &provider, open };
+        let (doc, diagnostics) = Document::assemble_with(&text, name.as_deref(), &workspace, options);
+
+        let mut files = vec![File { uri: uri.to_owned(), path: None, text }];
+        files.extend(diagnostics.sources().map(|(path, text)| File {
+            uri: workspace.uri(path),
+            path: Some(path.to_owned()),
+            text: text.to_owned(),
+        }));
+
+        Self { doc, diagnostics, files, provider }
+    }
+
+    pub fn main(&self) -> &File {
+        &self.files[0]
+    }
+
+    /// A file by its resolved path, `None` for the main file.
+    pub fn file(&self, path: Option<&str>) -> Option<&File> {
+        self.files.iter().find(|file| file.path.as_deref() == path)
+    }
+
+    /// The diagnostics of the main file.
+    ///
+    /// Problems in included files are shown at the `.include` line.
+    pub fn lsp_diagnostics(&self) -> Vec<Value> {
+        self.diagnostics
+            .iter()
+            .map(|diagnostic| self.lsp_diagnostic(diagnostic))
+            .collect()
+    }
+
+    fn lsp_diagnostic(&self, diagnostic: &Diagnostic) -> Value {
+        let mut message = diagnostic.message.summary();
+        for note in diagnostic.message.notes() {
+            message.push_str("\nNote: ");
+            message.push_str(&note);
+        }
+        if let Some(help) = diagnostic.message.help() {
+            message.push_str("\nHelp: ");
+            message.push_str(&help);
+        }
+
+        let mut related = diagnostic.related
+            .iter()
+            .filter_map(|related| Some(json!({
+                "location": self.location(&related.span)?,
+                "message": related.message,
+            })))
+            .collect::<Vec<_>>();
+
+        let range = match diagnostic.span.location.file {
+            None => self.range(&diagnostic.span).unwrap_or_else(|| line_range(&self.main().text, 0)),
+            Some(ref file) => {
+                if let Some(location) = self.location(&diagnostic.span) {
+                    related.insert(0, json!({ "location": location, "message": "the problem is here" }));
+                }
+                message = format!("{}: {}", file, message);
+                line_range(&self.main().text, self.include_line(file).unwrap_or(0))
+            }
+        };
+
+        json!({
+            "range": range,
+            "severity": match diagnostic.severity {
+                Severity::Error => 1,
+                Severity::Warning => 2,
+            },
+            "source": "kasm",
+            "message": message,
+            "relatedInformation": related,
+        })
+    }
+
+    /// The LSP location of a span.
+    pub fn location(&self, span: &Span) -> Option<Value> {
+        let file = self.file(span.location.file.as_deref())?;
+        Some(json!({ "uri": file.uri, "range": self.range(span)? }))
+    }
+
+    fn range(&self, span: &Span) -> Option<Value> {
+        let file = self.file(span.location.file.as_deref())?;
+        let line = span.location.line.checked_sub(1)?;
+
+        Some(match span.columns {
+            Some(ref columns) => range(&file.text, line, columns.clone()),
+            None => line_range(&file.text, line),
+        })
+    }
+
+    /// The line of the main file that includes `file`, if it includes it directly.
+    fn include_line(&self, file: &str) -> Option<usize> {
+        self.main().text.lines().position(|line| {
+            match code_line::split_tokens(line).as_slice() {
+                [directive, path] if directive.value.eq_ignore_ascii_case(".include") => literal::parse_string(path.value)
+                    .map(|path| self.provider.resolve(&path, None) == file)
+                    .unwrap_or(false),
+                _ => false
+            }
+        })
+    }
+}
+
+/// Loads open documents from the editor, and all other files from the file system.
+struct Workspace<'a> {
+    fs: &'a FileSystem,
+    open: &'a HashMap<String, String>,
+}
+
+impl SourceProvider for Workspace<'_> {
+    fn resolve(&self, path: &str, from: Option<&str>) -> String {
+        self.fs.resolve(path, from)
+    }
+
+    fn load(&self, path: &str) -> std::io::Result<String> {
+        match self.open.get(&self.uri(path)) {
+            Some(text) => Ok(text.clone()),
+            None => self.fs.load(path),
+        }
+    }
+}
+
+impl Workspace<'_> {
+    /// The URI of a path, as the editor spelled it if the file is open.
+    fn uri(&self, path: &str) -> String {
+        self.open
+            .keys()
+            .find(|uri| uri_to_path(uri).as_deref() == Some(Path::new(path)))
+            .cloned()
+            .unwrap_or_else(|| path_to_uri(path))
+    }
+}
+
+/// The range of characters in a line, the protocol counts columns in UTF-16.
+pub fn range(text: &str, line: usize, columns: Range<usize>) -> Value {
+    let s = text.lines().nth(line).unwrap_or_default();
+    json!({
+        "start": { "line": line, "character": to_utf16(s, columns.start) },
+        "end": { "line": line, "character": to_utf16(s, columns.end) },
+    })
+}
+
+fn line_range(text: &str, line: usize) -> Value {
+    let len = text.lines().nth(line).map(|s| s.chars().count()).unwrap_or(0);
+    range(text, line, 0..len)
+}
+
+fn to_utf16(line: &str, column: usize) -> usize {
+    line.chars().take(column).map(char::len_utf16).sum()
+}
+
+/// Converts a UTF-16 column of the protocol into a character column.
+pub fn from_utf16(line: &str, character: usize) -> usize {
+    let mut utf16 = 0;
+    line
+        .chars()
+        .take_while(|c| {
+            utf16 += c.len_utf16();
+            utf16 <= character
+        })
+        .count()
+}
+
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes.get(i + 1..i + 3) {
+            Some(hex) if bytes[i] == b'%' => std::str::from_utf8(hex)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    Some(PathBuf::from(String::from_utf8_lossy(&decoded).into_owned()))
+}
+
+pub fn path_to_uri(path: &str) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => uri.push(byte as char),
+            byte => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
@@ -0,0 +1,433 @@
+//! A language server for editors that speak the language server protocol,
+//! like VS Code or Neovim.
+//!
+//! The server communicates over stdin and stdout, and provides diagnostics, go to definition,
+//! find references, hover, completion and rename.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{BufRead, Write};
+
+use num_traits::FromPrimitive;
+use serde_json::{json, Value};
+use strum::VariantNames;
+
+use kasm::Result;
+use kasm::instruction::{Addressing, Instruction};
+use kasm::interrupt::Interrupt;
+use kasm::lexer::code_line::{self, CodeLine};
+use kasm::lexer::directive::Directive;
+use kasm::lexer::literal::Literal;
+use kasm::lexer::options::Options;
+
+use crate::rpc;
+use analysis::Analysis;
+use symbols::Occurrence;
+
+mod analysis;
+mod symbols;
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INVALID_REQUEST: i64 = -32600;
+
+type Response = std::result::Result<Value, (i64, String)>;
+
+/// Runs the language server till the client exits it.
+pub fn run(options: &Options) -> Result<()> {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    Server::new(stdin.lock(), stdout.lock(), options.clone()).run()
+}
+
+pub struct Server<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+    options: Options,
+    /// The text of all open documents by their URI.
+    documents: HashMap<String, String>,
+    shutdown: bool,
+}
+
+/// A position in an open document, with the column in characters.
+struct Position {
+    uri: String,
+    line: usize,
+    column: usize,
+}
+
+impl<R: BufRead, W: Write> Server<R, W> {
+    pub fn new(input: R, output: W, options: Options) -> Self {
+        Self {
+            input,
+            output,
+            options,
+            documents: HashMap::new(),
+            shutdown: false,
+        }
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        while let Some(message) = rpc::read_message(&mut self.input)? {
+            let method = match message.get("method").and_then(Value::as_str) {
+                Some(method) => method,
+                // responses to requests of the server, which never sends any
+                None => continue,
+            };
+            let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+            match message.get("id") {
+                Some(id) => {
+                    let response = match self.shutdown {
+                        true => Err((INVALID_REQUEST, "The server was shut down".to_owned())),
+                        false => self.handle_request(method, &params),
+                    };
+                    let message = match response {
+                        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                        Err((code, message)) => json!({
+                            "jsonrpc": "2.0",
+                            "id": id,
+                            "error": { "code": code, "message": message },
+                        }),
+                    };
+                    rpc::write_message(&mut self.output, &message)?;
+                }
+                None if method == "exit" => return Ok(()),
+                None => self.handle_notification(method, &params)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_request(&mut self, method: &str, params: &Value) -> Response {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": { "triggerCharacters": ["."] },
+                    "renameProvider": { "prepareProvider": true },
+                },
+                "serverInfo": { "name": "kasm" },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/references" => self.references(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            "textDocument/prepareRename" => self.prepare_rename(params),
+            "textDocument/rename" => self.rename(params),
+            method => Err((METHOD_NOT_FOUND, format!("The method {} is not supported", method))),
+        }
+    }
+
+    fn handle_notification(&mut self, method: &str, params: &Value) -> Result<()> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_owned();
+
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri, text.to_owned());
+            }
+            "textDocument/didChange" => {
+                // the server asked for full documents, so the last change contains the whole text
+                if let Some(text) = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str()) {
+                    self.documents.insert(uri, text.to_owned());
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.publish_diagnostics(&uri, Vec::new())?;
+            }
+            _ => return Ok(()),
+        }
+
+        // open documents may include each other
+        let uris = self.documents.keys().cloned().collect::<Vec<_>>();
+        for uri in uris {
+            let diagnostics = self.analyze(&uri).lsp_diagnostics();
+            self.publish_diagnostics(&uri, diagnostics)?;
+        }
+        Ok(())
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str, diagnostics: Vec<Value>) -> Result<()> {
+        let message = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        });
+        Ok(rpc::write_message(&mut self.output, &message)?)
+    }
+
+    fn definition(&self, params: &Value) -> Response {
+        let position = self.position(params)?;
+        let analysis = self.analyze(&position.uri);
+        let name = match self.jump_point_at(&analysis, &position) {
+            Some(occurrence) => occurrence.name,
+            None => return Ok(Value::Null),
+        };
+
+        Ok(Value::Array(occurrences(&analysis, &name, |o| o.declaration)))
+    }
+
+    fn references(&self, params: &Value) -> Response {
+        let position = self.position(params)?;
+        let include_declaration = params["context"]["includeDeclaration"].as_bool().unwrap_or(true);
+        let analysis = self.analyze(&position.uri);
+        let name = match self.jump_point_at(&analysis, &position) {
+            Some(occurrence) => occurrence.name,
+            None => return Ok(Value::Null),
+        };
+
+        Ok(Value::Array(occurrences(&analysis, &name, |o| include_declaration || !o.declaration)))
+    }
+
+    fn hover(&self, params: &Value) -> Response {
+        let position = self.position(params)?;
+        let analysis = self.analyze(&position.uri);
+        let text = &analysis.main().text;
+        let line = text.lines().nth(position.line).unwrap_or_default();
+
+        if let Some(occurrence) = self.jump_point_at(&analysis, &position) {
+            let address = analysis.doc
+                .as_ref()
+                .map(|doc| doc.source_map())
+                .and_then(|source_map| source_map
+                    .all_labels()
+                    .find(|&(_, name)| name == occurrence.name)
+                    .map(|(addr, _)| addr));
+            let contents = match address {
+                Some(address) => format!("jump point `.{}` at address {}", occurrence.name, address),
+                None => format!("jump point `.{}`", occurrence.name),
+            };
+            return Ok(hover(contents, analysis::range(text, occurrence.line, occurrence.columns)));
+        }
+
+        let tokens = code_line::split_tokens(line);
+        let (i, token) = match symbols::token_at(line, position.column) {
+            Some(token) => token,
+            None => return Ok(Value::Null),
+        };
+        let range = analysis::range(text, position.line, token.columns.clone());
+
+        let contents = match i {
+            0 => instruction(token.value).map(|inst| format!(
+                "**{}** (instruction code {})\n\n{}",
+                inst,
+                inst as u64,
+                inst.description(),
+            )),
+            1 if matches!(instruction(tokens[0].value), Some(Instruction::INT)) => number(token.value)
+                .and_then(Interrupt::from_i64)
+                .map(|int| format!("**{}** (interrupt {})\n\n{}", int, int as u64, int.description())),
+            1 => match CodeLine::from_tokens(line, &tokens) {
+                Ok(Some(CodeLine::DoubleToken(_, argument, addressing))) => match addressing {
+                    Addressing::Direct => None,
+                    Addressing::Indirect => Some(format!(
+                        "indirect addressing, accesses `Rx[Rx[{}]]`",
+                        argument.value,
+                    )),
+                    Addressing::Indexed(m) => Some(format!(
+                        "indexed addressing, accesses `Rx[{} + Rx[{}]]`",
+                        argument.value, m,
+                    )),
+                },
+                _ => None,
+            },
+            _ => None,
+        };
+
+        Ok(contents.map(|contents| hover(contents, range)).unwrap_or(Value::Null))
+    }
+
+    fn completion(&self, params: &Value) -> Response {
+        let position = self.position(params)?;
+        let analysis = self.analyze(&position.uri);
+        let text = &analysis.main().text;
+        let line = text.lines().nth(position.line).unwrap_or_default();
+
+        // only the code before the cursor decides what may follow
+        let before = line.chars().take(position.column).collect::<String>();
+        if code_line::split_comment(&before).1.is_some() {
+            return Ok(Value::Null);
+        }
+        let tokens = code_line::split_tokens(&before);
+        let (index, start) = match tokens.last() {
+            Some(token) if token.columns.end == position.column => (tokens.len() - 1, token.columns.start),
+            _ => (tokens.len(), position.column),
+        };
+        let range = analysis::range(text, position.line, start..position.column);
+
+        let mut items = Vec::new();
+        let mut item = |label: String, kind: u8, detail: String, documentation: Option<&str>| {
+            let mut item = json!({
+                "label": label,
+                "kind": kind,
+                "detail": detail,
+                "textEdit": { "range": range, "newText": label },
+            });
+            if let Some(documentation) = documentation {
+                item["documentation"] = json!(documentation);
+            }
+            items.push(item);
+        };
+
+        if index == 0 {
+            for name in Instruction::VARIANTS {
+                if let Some(inst) = instruction(name) {
+                    item(name.to_lowercase(), 14, format!("instruction code {}", inst as u64), Some(inst.description()));
+                }
+            }
+            for name in Directive::NAMES.iter().chain(&[".macro", ".endm"]) {
+                item(name.to_string(), 14, "directive".to_owned(), None);
+            }
+            let macros = analysis.files
+                .iter()
+                .flat_map(|file| symbols::macros(&file.text))
+                .collect::<BTreeSet<_>>();
+            for name in macros {
+                item(name, 3, "macro".to_owned(), None);
+            }
+        } else if index == 1 && matches!(instruction(tokens[0].value), Some(Instruction::INT)) {
+            for (code, name) in Interrupt::VARIANTS.iter().enumerate() {
+                if let Some(int) = Interrupt::from_usize(code) {
+                    item(code.to_string(), 20, name.to_string(), Some(int.description()));
+                }
+            }
+        } else {
+            let declarations = analysis.files
+                .iter()
+                .flat_map(|file| symbols::jump_points(&file.text))
+                .filter(|occurrence| occurrence.declaration)
+                .map(|occurrence| occurrence.name)
+                .collect::<BTreeSet<_>>();
+            for name in declarations {
+                item(format!(".{}", name), 18, "jump point".to_owned(), None);
+            }
+        }
+
+        Ok(Value::Array(items))
+    }
+
+    fn prepare_rename(&self, params: &Value) -> Response {
+        let position = self.position(params)?;
+        let analysis = self.analyze(&position.uri);
+
+        Ok(match self.jump_point_at(&analysis, &position) {
+            Some(occurrence) => json!({
+                "range": analysis::range(&analysis.main().text, occurrence.line, occurrence.columns),
+                "placeholder": occurrence.name,
+            }),
+            None => Value::Null,
+        })
+    }
+
+    fn rename(&self, params: &Value) -> Response {
+        let position = self.position(params)?;
+        let new_name = params["newName"].as_str().unwrap_or_default();
+        let new_name = new_name.strip_prefix('.').unwrap_or(new_name);
+        if !symbols::is_valid_name(new_name) {
+            return Err((INVALID_PARAMS, format!("`.{}` is not a valid jump point", new_name)));
+        }
+
+        let analysis = self.analyze(&position.uri);
+        let name = match self.jump_point_at(&analysis, &position) {
+            Some(occurrence) => occurrence.name,
+            None => return Ok(Value::Null),
+        };
+
+        let mut changes = serde_json::Map::new();
+        for file in analysis.files.iter() {
+            let edits = symbols::jump_points(&file.text)
+                .into_iter()
+                .filter(|occurrence| occurrence.name == name)
+                .map(|occurrence| json!({
+                    "range": analysis::range(&file.text, occurrence.line, occurrence.columns),
+                    "newText": new_name,
+                }))
+                .collect::<Vec<_>>();
+            if !edits.is_empty() {
+                changes.insert(file.uri.clone(), Value::Array(edits));
+            }
+        }
+
+        Ok(json!({ "changes": changes }))
+    }
+
+    fn analyze(&self, uri: &str) -> Analysis {
+        Analysis::new(uri, &self.documents, &self.options)
+    }
+
+    fn position(&self, params: &Value) -> std::result::Result<Position, (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str();
+        let line = params["position"]["line"].as_u64();
+        let character = params["position"]["character"].as_u64();
+
+        match (uri, line, character) {
+            (Some(uri), Some(line), Some(character)) => {
+                let text = self.documents
+                    .get(uri)
+                    .ok_or_else(|| (INVALID_PARAMS, format!("The document {} is not open", uri)))?;
+                let column = analysis::from_utf16(
+                    text.lines().nth(line as usize).unwrap_or_default(),
+                    character as usize,
+                );
+                Ok(Position { uri: uri.to_owned(), line: line as usize, column })
+            }
+            _ => Err((INVALID_PARAMS, "Expected a text document and a position".to_owned())),
+        }
+    }
+
+    fn jump_point_at(&self, analysis: &Analysis, position: &Position) -> Option<Occurrence> {
+        symbols::jump_points(&analysis.main().text)
+            .into_iter()
+            .find(|occurrence| occurrence.contains(position.line, position.column))
+    }
+}
+
+/// The locations of all occurrences of a jump point in all files of the program.
+fn occurrences<F: Fn(&Occurrence) -> bool>(analysis: &Analysis, name: &str, filter: F) -> Vec<Value> {
+    analysis.files
+        .iter()
+        .flat_map(|file| symbols::jump_points(&file.text)
+            .into_iter()
+            .filter(|occurrence| occurrence.name == name && filter(occurrence))
+            .map(move |occurrence| json!({
+                "uri": file.uri,
+                "range": analysis::range(&file.text, occurrence.line, occurrence.columns),
+            })))
+        .collect()
+}
+
+fn hover(contents: String, range: Value) -> Value {
+    json!({
+        "contents": { "kind": "markdown", "value": contents },
+        "range": range,
+    })
+}
+
+/// An instruction by its name or its instruction code.
+fn instruction(token: &str) -> Option<Instruction> {
+    token
+        .to_uppercase()
+        .parse::<Instruction>()
+        .ok()
+        .or_else(|| number(token).and_then(Instruction::from_i64))
+}
+
+fn number(token: &str) -> Option<i64> {
+    match Literal::parse(token) {
+        Ok(Some(Literal::Unsigned(val))) => Some(val as i64),
+        Ok(Some(Literal::Signed(val))) => Some(val),
+        _ => None,
+    }
+}
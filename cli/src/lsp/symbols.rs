@@ -0,0 +1,99 @@
+//! Finds names in the source code by their tokens, so they are found even if the
+//! document does not assemble.
+
+use std::ops::Range;
+
+use kasm::lexer::code_line;
+use kasm::lexer::directive::Directive;
+use kasm::lexer::macros::Macro;
+use kasm::lexer::spanned::Spanned;
+
+/// A jump point in the source code.
+#[derive(Clone, Debug)]
+pub struct Occurrence {
+    /// The line, starting at 0.
+    pub line: usize,
+    /// The characters of the name, without the leading `.`.
+    pub columns: Range<usize>,
+    pub name: String,
+    /// Whether the jump point is declared here, like `.loop:`.
+    pub declaration: bool,
+}
+
+impl Occurrence {
+    /// Whether the occurrence covers a character column, including the `.` and the column after the name.
+    pub fn contains(&self, line: usize, column: usize) -> bool {
+        self.line == line && self.columns.start <= column + 1 && column <= self.columns.end
+    }
+}
+
+/// All declarations and uses of jump points, including those in expressions.
+pub fn jump_points(text: &str) -> Vec<Occurrence> {
+    let mut occurrences = Vec::new();
+
+    for (line, s) in text.lines().enumerate() {
+        let tokens = code_line::split_tokens(s);
+
+        for (i, token) in tokens.iter().enumerate() {
+            if token.value.starts_with(['"', '\'']) || (i == 0 && is_keyword(&tokens)) {
+                continue;
+            }
+
+            let chars = token.value.chars().collect::<Vec<_>>();
+            let mut j = 0;
+            while j < chars.len() {
+                if chars[j] != '.' || (j > 0 && is_name_char(chars[j - 1])) {
+                    j += 1;
+                    continue;
+                }
+
+                let start = j + 1;
+                let end = start + chars[start..].iter().take_while(|&&c| is_name_char(c)).count();
+                if end > start {
+                    occurrences.push(Occurrence {
+                        line,
+                        columns: token.columns.start + start..token.columns.start + end,
+                        name: chars[start..end].iter().collect(),
+                        declaration: tokens.len() == 1 && j == 0 && chars.get(end) == Some(&':') && end + 1 == chars.len(),
+                    });
+                }
+                j = end;
+            }
+        }
+    }
+
+    occurrences
+}
+
+/// The names of all macros defined in the source code.
+pub fn macros(text: &str) -> Vec<String> {
+    text
+        .lines()
+        .map(code_line::split_tokens)
+        .filter(|tokens| Macro::is_start(tokens))
+        .filter_map(|tokens| tokens.get(1).map(|name| name.value.to_owned()))
+        .collect()
+}
+
+/// The token at a character column together with its index in the line,
+/// the column right after a token still belongs to it.
+pub fn token_at(line: &str, column: usize) -> Option<(usize, Spanned<&str>)> {
+    code_line::split_tokens(line)
+        .into_iter()
+        .enumerate()
+        .find(|(_, token)| token.columns.start <= column && column <= token.columns.end)
+}
+
+/// Whether a name can be used as a jump point.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(is_name_char)
+}
+
+/// Whether the first token is a directive or starts or ends a macro.
+pub fn is_keyword(tokens: &[Spanned<&str>]) -> bool {
+    Directive::is_directive(tokens) || Macro::is_start(tokens) || Macro::is_end(tokens)
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
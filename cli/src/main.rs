@@ -0,0 +1,417 @@
+#![allow(non_snake_case)]
+
+use std::io::Read;
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use clap::{Parser, Subcommand};
+
+use kasm::{cpu::{self, CPU, ExecResult}, Error, RAM, Result};
+use kasm::diagnostic::{Diagnostics, Message};
+use kasm::formatter::Formatter;
+use kasm::image::Image;
+use kasm::lexer::Document;
+use kasm::lexer::options::Options;
+use kasm::linker::Linker;
+use kasm::object::Object;
+use kasm::source::{FileSystem, Location, SourceProvider};
+
+mod dap;
+mod gdb;
+mod lsp;
+mod rpc;
+mod shell;
+
+#[derive(Parser)]
+#[command(name = "kasm", version, about = "A Klett asm assembler and emulator")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Rejects jump points that only differ in case, like `.loop` and `.LOOP`
+    #[arg(long, global = true)]
+    strict: bool,
+    /// The number of words in the data memory of the CPU
+    #[arg(long, global = true, default_value = "1024")]
+    memory_size: usize,
+    /// The number of values the stack of the CPU can hold
+    #[arg(long, global = true, default_value = "256")]
+    stack_depth: usize,
+    /// What arithmetic does when the result does not fit into A: `wrap`, `saturate` or `trap`
+    #[arg(long, global = true, default_value = "wrap")]
+    overflow: cpu::Overflow,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Assembles a program and runs it till the end (END)
+    Run {
+        /// The source file or program image (`.kimg`), or `-` to read source code from stdin
+        file: PathBuf,
+        /// The maximum amount of instructions executed before giving up
+        #[arg(long, default_value = "1000000")]
+        max_steps: NonZeroU64,
+    },
+    /// Assembles a program without running it and reports all errors
+    Check {
+        /// The source file, or `-` to read from stdin
+        file: PathBuf,
+    },
+    /// Assembles a program and prints the resulting RAM
+    Asm {
+        /// The source file, or `-` to read from stdin
+        file: PathBuf,
+        /// Prints every source line next to its address and the words it was assembled to,
+        /// followed by a table of all symbols
+        #[arg(short, long)]
+        listing: bool,
+    },
+    /// Assembles a program into a program image, that can be run without the source code
+    Image {
+        /// The source file, or `-` to read from stdin
+        file: PathBuf,
+        /// The image file, defaults to the source file with a `.kimg` extension
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Leaves out the symbols and the source map
+        #[arg(long)]
+        strip: bool,
+    },
+    /// Formats source files in place
+    Fmt {
+        /// The source files, or `-` to format stdin to stdout
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Only checks whether the files are formatted, and fails if they are not
+        #[arg(long)]
+        check: bool,
+    },
+    /// Turns a program back into source code
+    Disasm {
+        /// The program image (`.kimg`) or source file, or `-` to read source code from stdin
+        file: PathBuf,
+    },
+    /// Assembles a program into a relocatable object, that can be linked with others
+    Object {
+        /// The source file, or `-` to read from stdin
+        file: PathBuf,
+        /// The object file, defaults to the source file with a `.kobj` extension
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Links objects and source files together and prints the resulting RAM
+    Link {
+        /// Objects (`.kobj`) and source files, the program starts with the first one unless one sets `.entry`
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Runs the linked program instead of printing it
+        #[arg(long)]
+        run: bool,
+        /// Writes the linked program to a program image instead of printing it
+        #[arg(short, long, conflicts_with = "run")]
+        output: Option<PathBuf>,
+        /// The maximum amount of instructions executed before giving up
+        #[arg(long, default_value = "1000000")]
+        max_steps: NonZeroU64,
+    },
+    /// Starts a language server, that communicates over stdin and stdout
+    Lsp,
+    /// Starts a debug adapter, that communicates over stdin and stdout, or a TCP port
+    Dap {
+        /// Listens for a debugger on this port on localhost instead of using stdin and stdout
+        #[arg(long)]
+        port: Option<u16>,
+    },
+    /// Starts a GDB remote serial protocol stub on a local TCP port, for debugging with GDB
+    Gdb {
+        /// A source file or program image
+        file: PathBuf,
+        /// The port GDB connects to on localhost
+        #[arg(long, default_value = "1234")]
+        port: u16,
+    },
+    /// Starts an interactive debugger shell
+    Shell {
+        /// A source file to load on startup
+        file: Option<PathBuf>,
+        /// The maximum amount of instructions executed by `/continue` and `/run`
+        #[arg(long, default_value = "1000000")]
+        max_steps: NonZeroU64,
+    },
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let options = Options {
+        strict_jump_points: cli.strict,
+    };
+
+    let config = cpu::Config {
+        memory_size: cli.memory_size,
+        stack_depth: cli.stack_depth,
+        overflow: cli.overflow,
+    };
+
+    if let Err(err) = execute(cli.command, &options, &config) {
+        report(&err);
+        exit(exit_code(&err));
+    }
+}
+
+fn execute(command: Command, options: &Options, config: &cpu::Config) -> Result<()> {
+    match command {
+        Command::Run { file, max_steps } => {
+            let image = load_image(&file, options)?;
+            let source_map = image.source_map.unwrap_or_default();
+            let mut cpu = CPU::new(image.ram, config.clone(), std::io::stdout());
+            *cpu.BZ_mut() = image.entry;
+
+            match cpu.step_to_end(max_steps).map_err(|err| source_map.annotate(err))? {
+                ExecResult::NotFinished => Err(Error::TooManySteps(max_steps.get())),
+                _ => Ok(())
+            }
+        }
+        Command::Check { file } => {
+            assemble(&file, options)?;
+            Ok(())
+        }
+        Command::Asm { file, listing: true } => {
+            let (doc, source, diagnostics) = assemble_with_source(&file, options)?;
+            print!("{}", doc.listing(&source, &diagnostics));
+            Ok(())
+        }
+        Command::Asm { file, listing: false } => {
+            let doc = assemble(&file, options)?;
+            print_ram(&link_document(&file, &doc)?);
+            Ok(())
+        }
+        Command::Image { file, output, strip } => {
+            let mut image = load_image(&file, options)?;
+            if strip {
+                image.strip();
+            }
+
+            let output = output.unwrap_or_else(|| default_output(&file, "kimg"));
+            image.save(&mut std::fs::File::create(output)?)
+        }
+        Command::Fmt { files, check } => {
+            let formatter = Formatter::default();
+            let mut result = Ok(());
+
+            for file in files.iter() {
+                let source = read_source(file)?;
+                if check {
+                    let checked = formatter.check(&source).map_err(|err| match err {
+                        Error::NotFormatted { line } if file.as_os_str() != "-" => Error::NotFormatted {
+                            line: Location::new(Some(file.display().to_string().into()), line.line),
+                        },
+                        err => err,
+                    });
+                    // every file but the last is reported here, the last one by `main`
+                    if let Err(err) = std::mem::replace(&mut result, checked) {
+                        report(&err);
+                    }
+                } else if file.as_os_str() == "-" {
+                    print!("{}", formatter.format(&source));
+                } else {
+                    let formatted = formatter.format(&source);
+                    if formatted != source {
+                        std::fs::write(file, formatted)?;
+                    }
+                }
+            }
+
+            result
+        }
+        Command::Disasm { file } => {
+            print!("{}", load_image(&file, options)?.disassemble()?);
+            Ok(())
+        }
+        Command::Object { file, output } => {
+            let object = assemble(&file, options)?.object()?;
+            let output = output.unwrap_or_else(|| default_output(&file, "kobj"));
+            std::fs::write(output, object.to_string())?;
+            Ok(())
+        }
+        Command::Link { files, run, output, max_steps } => {
+            let mut linker = Linker::new();
+            for file in files.iter() {
+                let object = match file.extension() {
+                    Some(extension) if extension == "kobj" => std::fs::read_to_string(file)?.parse::<Object>()?,
+                    _ => assemble(file, options)?.object()?,
+                };
+                linker.add(file.display().to_string(), object);
+            }
+            let linked = linker.link()?;
+            let ram = linked.ram;
+
+            if let Some(output) = output {
+                let image = Image { entry: linked.entry, symbols: Some(linked.symbols), ..Image::new(ram) };
+                image.save(&mut std::fs::File::create(output)?)
+            } else if run {
+                let mut cpu = CPU::new(ram, config.clone(), std::io::stdout());
+                *cpu.BZ_mut() = linked.entry;
+                match cpu.step_to_end(max_steps)? {
+                    ExecResult::NotFinished => Err(Error::TooManySteps(max_steps.get())),
+                    _ => Ok(())
+                }
+            } else {
+                print_ram(&ram);
+                Ok(())
+            }
+        }
+        Command::Lsp => lsp::run(options),
+        Command::Dap { port } => dap::run(port, options, config),
+        Command::Gdb { file, port } => gdb::run(&file, port, options, config),
+        Command::Shell { file, max_steps } => {
+            let mut shell = shell::Shell::new(max_steps, options.clone(), config.clone());
+            if let Some(file) = file {
+                shell.load(file)?;
+            }
+            shell.run()
+        }
+    }
+}
+
+/// Assembles a file and reports all diagnostics to stderr.
+///
+/// Included files are resolved relative to the file, or to the working directory for stdin.
+fn assemble(file: &Path, options: &Options) -> Result<Document> {
+    assemble_with_source(file, options).map(|(doc, _, _)| doc)
+}
+
+/// Same as [`assemble`], but also returns the source code, and the diagnostics
+/// that contain the source code of all included files.
+fn assemble_with_source(file: &Path, options: &Options) -> Result<(Document, String, Diagnostics)> {
+    let source = read_source(file)?;
+    let (doc, diagnostics) = if file.as_os_str() == "-" {
+        Document::assemble_with(&source, None, &FileSystem::default(), options)
+    } else {
+        let provider = FileSystem::new(file.parent().unwrap_or_else(|| Path::new("")));
+        let path = file
+            .file_name()
+            .map(|name| provider.resolve(&name.to_string_lossy(), None));
+        Document::assemble_with(&source, path.as_deref(), &provider, options)
+    };
+
+    if !diagnostics.is_empty() {
+        eprint!("{}", diagnostics.render(&source));
+    }
+
+    match doc {
+        Some(doc) => Ok((doc, source, diagnostics)),
+        None => Err(Error::Assembly(diagnostics)),
+    }
+}
+
+/// Links a single document, to report jump points it imports from other objects.
+fn link_document(file: &Path, doc: &Document) -> Result<RAM> {
+    if !doc.has_imports() {
+        return Ok(doc.as_ram());
+    }
+
+    Ok(Linker::new()
+        .add(file.display().to_string(), doc.object()?)
+        .link()?
+        .ram)
+}
+
+/// Loads a program image, or assembles a source file into one.
+fn load_image(file: &Path, options: &Options) -> Result<Image> {
+    if matches!(file.extension(), Some(extension) if extension == "kimg") {
+        return Image::load(&mut std::fs::File::open(file)?);
+    }
+
+    let doc = assemble(file, options)?;
+    Ok(Image {
+        ram: link_document(file, &doc)?,
+        ..doc.image()
+    })
+}
+
+/// The source file with another extension, or `out` for stdin.
+fn default_output(file: &Path, extension: &str) -> PathBuf {
+    if file.as_os_str() == "-" {
+        PathBuf::from("out").with_extension(extension)
+    } else {
+        file.with_extension(extension)
+    }
+}
+
+fn print_ram(ram: &RAM) {
+    for (i, (inst, val)) in ram.iter().enumerate() {
+        println!("{:>4}: {} {}", i, inst, val);
+    }
+}
+
+fn report(err: &Error) {
+    match err {
+        // the diagnostics were already rendered together with the source code
+        Error::Assembly(_) => {}
+        err => eprintln!("{}", err),
+    }
+}
+
+fn read_source(file: &Path) -> Result<String> {
+    if file.as_os_str() == "-" {
+        let mut source = String::new();
+        std::io::stdin().read_to_string(&mut source)?;
+        Ok(source)
+    } else {
+        Ok(std::fs::read_to_string(file)?)
+    }
+}
+
+/// Every error kind gets its own exit code, so scripts can tell them apart.
+/// Code 1 is left to panics and code 2 to invalid command line arguments.
+fn exit_code(err: &Error) -> i32 {
+    match err {
+        Error::InvalidInstruction { .. } => 10,
+        Error::InvalidInterrupt { .. } => 11,
+        Error::DivideByZero { .. } => 12,
+        Error::InvalidRxIndex { .. } => 13,
+        Error::NoMoreInstructions { .. } => 14,
+        Error::InLine { err, .. } => exit_code(err),
+        Error::TooManySteps(_) => 15,
+        Error::InvalidMemoryAddress { .. } => 16,
+        Error::InvalidPointedRxIndex { .. } => 17,
+        Error::StackOverflow { .. } => 18,
+        Error::StackUnderflow { .. } => 19,
+        Error::UndefinedJumpPoint { .. } => 20,
+        Error::ParsingFailed { .. } => 21,
+        Error::InvalidTokenArrangement { .. } => 22,
+        Error::DuplicateJumpPoint { .. } => 23,
+        Error::JumpPointDiffersOnlyInCase { .. } => 24,
+        Error::StringDoesNotFit { .. } => 25,
+        Error::UndefinedConstant { .. } => 26,
+        Error::CyclicConstant { .. } => 27,
+        Error::DuplicateConstant { .. } => 28,
+        Error::ExpressionFailed { .. } => 29,
+        Error::UnclosedMacro { .. } => 40,
+        Error::UnexpectedEndMacro { .. } => 41,
+        Error::NestedMacro { .. } => 42,
+        Error::DuplicateMacro { .. } => 43,
+        Error::MacroArgumentCount { .. } => 44,
+        Error::MacroRecursionLimit { .. } => 45,
+        Error::IncludeFailed { .. } => 50,
+        Error::IncludeCycle { .. } => 51,
+        Error::InvalidObject { .. } => 60,
+        Error::Linking(_) => 61,
+        Error::ImportedJumpPointDeclared { .. } => 62,
+        Error::NotRelocatable { .. } => 63,
+        Error::DuplicateEntry { .. } => 64,
+        Error::InvalidImage(_) => 70,
+        Error::NotDisassemblable { .. } => 71,
+        Error::NotFormatted { .. } => 72,
+        Error::ArithmeticOverflow { .. } => 80,
+        Error::Assembly(diagnostics) => diagnostics
+            .errors()
+            .find_map(|d| match d.message {
+                Message::Error(ref err) => Some(exit_code(err)),
+                Message::Warning(_) => None,
+            })
+            .unwrap_or(20),
+        Error::IO(_) => 30,
+    }
+}
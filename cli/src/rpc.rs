@@ -0,0 +1,42 @@
+//! The transport of the language server and the debug adapter protocol:
+//! JSON messages, each preceded by a `Content-Length` header.
+
+use std::io::{BufRead, Error, ErrorKind, Write};
+
+use serde_json::Value;
+
+/// Reads the next message, `None` if the client closed the connection.
+pub fn read_message(r: &mut impl BufRead) -> std::io::Result<Option<Value>> {
+    let mut len = None;
+
+    loop {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                len = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let len = len.ok_or_else(|| Error::new(ErrorKind::InvalidData, "The message has no valid Content-Length header"))?;
+    let mut body = vec![0; len];
+    r.read_exact(&mut body)?;
+
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| Error::new(ErrorKind::InvalidData, err))
+}
+
+pub fn write_message(w: &mut impl Write, message: &Value) -> std::io::Result<()> {
+    let body = message.to_string();
+    write!(w, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    w.flush()
+}
@@ -0,0 +1,312 @@
+use std::io::{BufRead, Write};
+use std::num::NonZeroU64;
+use std::path::PathBuf;
+
+use strum::VariantNames;
+
+use kasm::{cpu::{self, CPU, ExecResult}, Error, RAM, URS};
+use kasm::instruction::Instruction;
+use kasm::interrupt::Interrupt;
+use kasm::lexer::code_line::CodeLine;
+use kasm::lexer::code_token::CodeToken;
+use kasm::lexer::options::Options;
+use kasm::source::Location;
+use kasm::source_map::SourceMap;
+
+const HELP: &str = "\
+Commands:
+  /load <file>         Assembles a file and loads it into RAM
+  /reload              Assembles the last loaded file again
+  /step [n]            Executes the next n instructions (default 1)
+  /continue            Executes all instructions till the next break point (BP)
+  /run                 Executes all instructions till the end (END)
+  /reset               Resets all registers, the flags and the data memory to 0
+  /regs                Shows the registers A, BZ and Rx, and the flags
+  /ram [from] [to]     Shows the RAM (around BZ by default)
+  /mem [from] [to]     Shows the data memory (up to the last word that is not 0 by default)
+  /stack               Shows the stack and the return addresses of all active calls
+  /bz <n>              Sets BZ to n
+  /max-steps <n>       Sets the maximum amount of steps for /continue and /run
+  /instructions        Lists all instructions
+  /interrupts          Lists all interrupts
+  /help                Shows this help
+  /quit                Exits the shell
+Any other line is parsed as an instruction and executed immediately";
+
+type Result<T, E = ShellError> = std::result::Result<T, E>;
+
+#[derive(Debug, derive_more::Display, derive_more::From)]
+enum ShellError {
+    Kasm(Error),
+    #[display(fmt = "{}", _0)]
+    #[from(ignore)]
+    Usage(String),
+}
+
+impl From<std::io::Error> for ShellError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Kasm(Error::IO(err))
+    }
+}
+
+pub struct Shell {
+    cpu: CPU<std::io::Stdout>,
+    source_map: SourceMap,
+    file: Option<PathBuf>,
+    options: Options,
+    max_steps: NonZeroU64,
+}
+
+impl Shell {
+    pub fn new(max_steps: NonZeroU64, options: Options, config: cpu::Config) -> Self {
+        Self {
+            cpu: CPU::new(RAM::default(), config, std::io::stdout()),
+            source_map: SourceMap::default(),
+            file: None,
+            options,
+            max_steps,
+        }
+    }
+
+    pub fn run(&mut self) -> kasm::Result<()> {
+        let stdin = std::io::stdin();
+        let mut lines = stdin.lock().lines();
+
+        loop {
+            match self.source_map.line(self.cpu.BZ()) {
+                Some(line) => print!("kasm [BZ={}, line {}]> ", self.cpu.BZ(), line),
+                None => print!("kasm [BZ={}]> ", self.cpu.BZ()),
+            }
+            std::io::stdout().flush()?;
+
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+
+            match self.execute(line.trim()) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(ShellError::Kasm(err)) => crate::report(&self.source_map.annotate(err)),
+                Err(err) => eprintln!("{}", err),
+            }
+        }
+    }
+
+    /// Executes a single shell line and returns whether the shell should keep running.
+    fn execute(&mut self, line: &str) -> Result<bool> {
+        let mut parts = line.split_ascii_whitespace();
+        let command = match parts.next() {
+            Some(command) => command,
+            None => return Ok(true),
+        };
+        let args = parts.collect::<Vec<_>>();
+
+        match command {
+            "/load" => {
+                let file = args.first().map(PathBuf::from).ok_or_else(|| usage("/load <file>"))?;
+                self.load(file)?;
+            }
+            "/reload" => {
+                let file = self.file.clone().ok_or_else(|| ShellError::Usage("No file was loaded yet".to_owned()))?;
+                self.load(file)?;
+            }
+            "/step" => {
+                let n = parse_arg(args.first(), 1)?;
+                for _ in 0..n {
+                    let bz = self.cpu.BZ();
+                    let res = self.cpu.step()?;
+                    if self.handle_exec_result(res, bz)? {
+                        break;
+                    }
+                }
+            }
+            "/continue" => {
+                let res = self.cpu.step_to_breakpoint(self.max_steps)?;
+                // `BP` and `END` do not jump
+                self.handle_exec_result(res, self.cpu.BZ().saturating_sub(1))?;
+            }
+            "/run" => {
+                let res = self.cpu.step_to_end(self.max_steps)?;
+                self.handle_exec_result(res, self.cpu.BZ().saturating_sub(1))?;
+            }
+            "/reset" => self.cpu.reset_registers(),
+            "/regs" => self.print_registers(),
+            "/ram" => {
+                let bz = self.cpu.BZ() as usize;
+                let from = parse_arg(args.first(), bz.saturating_sub(5))?;
+                let to = parse_arg(args.get(1), bz + 5)?;
+                self.print_ram(from, to);
+            }
+            "/mem" => {
+                let last = self.cpu.memory().iter().rposition(|&word| word != 0).map_or(0, |i| i + 1);
+                let from = parse_arg(args.first(), 0)?;
+                let to = parse_arg(args.get(1), last)?;
+                self.print_memory(from, to);
+            }
+            "/stack" => self.print_stack(),
+            "/bz" => {
+                let bz = args.first().ok_or_else(|| usage("/bz <n>"))?;
+                *self.cpu.BZ_mut() = parse_arg(Some(bz), 0)?;
+            }
+            "/max-steps" => {
+                let max_steps = args.first().ok_or_else(|| usage("/max-steps <n>"))?;
+                self.max_steps = parse_arg(Some(max_steps), self.max_steps)?;
+            }
+            "/instructions" => print_variants(Instruction::VARIANTS),
+            "/interrupts" => print_variants(Interrupt::VARIANTS),
+            "/help" => println!("{}", HELP),
+            "/quit" | "/exit" => return Ok(false),
+            command if command.starts_with('/') => {
+                return Err(ShellError::Usage(format!(
+                    "Unknown command `{}`\nNote: Execute `/help` to get a list of all commands",
+                    command
+                )));
+            }
+            _ => self.execute_instruction(line)?,
+        }
+
+        Ok(true)
+    }
+
+    pub fn load(&mut self, file: PathBuf) -> kasm::Result<()> {
+        let image = crate::load_image(&file, &self.options)?;
+        println!("Loaded {} instructions from {}", image.ram.len(), file.display());
+
+        *self.cpu.ram_mut() = image.ram;
+        self.source_map = image.source_map.unwrap_or_default();
+        self.cpu.reset_registers();
+        *self.cpu.BZ_mut() = image.entry;
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn execute_instruction(&mut self, line: &str) -> Result<()> {
+        let code_line = match CodeLine::from_str(line) {
+            Ok(Some(code_line)) => code_line,
+            Ok(None) => return Ok(()),
+            Err(err) => return Err(Error::ParsingFailed { s: line.to_owned(), line: Location::line(1), err: err.value }.into()),
+        };
+        code_line
+            .check()
+            .map_err(|err| Error::InvalidTokenArrangement { line: Location::line(1), err: err.value })?;
+
+        // there is no program to resolve jump points against
+        let unresolved = |token: &CodeToken| {
+            matches!(token, CodeToken::JumpPoint(_) | CodeToken::JumpPointDeclaration(_) | CodeToken::Expr(_))
+        };
+        let is_unresolved = match code_line {
+            CodeLine::SingleToken(ref token) => unresolved(&token.value),
+            CodeLine::DoubleToken(ref inst, ref arg, _) => unresolved(&inst.value) || unresolved(&arg.value),
+        };
+        if is_unresolved {
+            return Err(ShellError::Usage("Jump points and expressions are not supported in the shell".to_owned()));
+        }
+
+        let (inst, value) = code_line.as_urs_irs();
+        let (inst, addressing) = Instruction::decode(inst)
+            .ok_or(Error::InvalidInstruction { inst, BZ: self.cpu.BZ() })?;
+
+        // instructions typed into the shell are not part of the RAM, so they
+        // should only move BZ if they jump
+        let bz = self.cpu.BZ();
+        let res = self.cpu.exec(inst, addressing, value)?;
+        if !inst.is_jump() && !matches!(inst, Instruction::RET) {
+            *self.cpu.BZ_mut() = bz;
+        }
+
+        self.handle_exec_result(res, bz)?;
+        Ok(())
+    }
+
+    /// Prints the outcome of executing the instruction at `addr`, and returns whether stepping should stop.
+    fn handle_exec_result(&mut self, res: ExecResult, addr: URS) -> Result<bool> {
+        match res {
+            ExecResult::Print(text) => {
+                self.cpu.println(&text)?;
+                Ok(false)
+            }
+            ExecResult::Ended => {
+                println!("Reached END at BZ={}", addr);
+                Ok(true)
+            }
+            ExecResult::HitBreakPoint => {
+                println!("Hit break point at BZ={}", addr);
+                Ok(true)
+            }
+            ExecResult::NotFinished => Err(Error::TooManySteps(self.max_steps.get()).into()),
+            ExecResult::None => Ok(false),
+        }
+    }
+
+    fn print_registers(&self) {
+        println!("A  = {}", self.cpu.A());
+        println!("BZ = {}", self.cpu.BZ());
+        println!("Flags: {}", self.cpu.flags());
+        for (i, rx) in self.cpu.Rx().iter().enumerate() {
+            println!("R{:<2}= {}", i, rx);
+        }
+    }
+
+    fn print_stack(&self) {
+        println!("Stack (top last): {:?}", self.cpu.stack());
+        for addr in self.cpu.return_addresses() {
+            let label = self.source_map
+                .all_labels()
+                .take_while(|&(label_addr, _)| label_addr < addr)
+                .last()
+                .map(|(_, name)| format!(" (called from .{})", name))
+                .unwrap_or_default();
+            println!("  returns to BZ={}{}", addr, label);
+        }
+    }
+
+    fn print_memory(&self, from: usize, to: usize) {
+        let memory = self.cpu.memory();
+        let to = to.min(memory.len());
+
+        for (i, word) in memory.iter().enumerate().take(to).skip(from) {
+            println!("M{:<4}= {}", i, word);
+        }
+    }
+
+    fn print_ram(&self, from: usize, to: usize) {
+        let ram = self.cpu.ram();
+        let to = to.min(ram.len());
+
+        for (i, &(inst, val)) in ram.iter().enumerate().take(to).skip(from) {
+            for label in self.source_map.labels(i as URS) {
+                println!("       .{}:", label);
+            }
+
+            let marker = if i as URS == self.cpu.BZ() { ">" } else { " " };
+            let line = self.source_map
+                .line(i as URS)
+                .map(|line| format!("  ; line {}", line))
+                .unwrap_or_default();
+            match Instruction::decode(inst) {
+                Some((inst, addressing)) => println!("{} {:>4}: {} {}{}", marker, i, inst, addressing.format(val), line),
+                None => println!("{} {:>4}: {} {}{}", marker, i, inst, val, line),
+            }
+        }
+    }
+}
+
+fn print_variants(variants: &[&str]) {
+    for (i, name) in variants.iter().enumerate() {
+        println!("{:>3}  {}", i, name);
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(arg: Option<&&str>, default: T) -> Result<T> {
+    match arg {
+        Some(arg) => arg
+            .parse()
+            .map_err(|_| ShellError::Usage(format!("Failed to parse the argument `{}`", arg))),
+        None => Ok(default),
+    }
+}
+
+fn usage(usage: &str) -> ShellError {
+    ShellError::Usage(format!("Usage: {}", usage))
+}
@@ -1,13 +1,19 @@
 use seed::{*, prelude::*};
 
+use kasm::diagnostic::{Diagnostic, Severity};
+
 use crate::Msg;
 
 #[wasm_bindgen]
 extern "C" {
     fn get_code() -> Option<String>;
+    fn set_code(code: &str);
     fn set_editor_font_size(font_size: u8);
     fn set_editor_error(row: usize, msg: String);
+    fn add_editor_annotation(row: usize, column: usize, msg: String, kind: &str);
     fn clear_editor_annotations();
+    fn set_editor_highlight(row: usize);
+    fn clear_editor_highlight();
 }
 
 pub struct Editor;
@@ -17,6 +23,12 @@ impl Editor {
         get_code()
     }
     
+    /// Replaces the code in the editor, the cursor stays where it was.
+    #[allow(unused_mut)]
+    pub fn set_code(&mut self, code: &str) {
+        set_code(code)
+    }
+
     #[allow(unused_mut)]
     pub fn set_font_size(&mut self, font_size: u8) {
         set_editor_font_size(font_size);
@@ -27,6 +39,49 @@ impl Editor {
         set_editor_error(row.saturating_sub(1), msg)
     }
     
+    #[allow(unused_mut)]
+    pub fn set_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        // the editor only shows the main file, included files are only reported in the console
+        if !diagnostic.span.location.is_main() {
+            return;
+        }
+
+        let row = diagnostic.span.location.line.saturating_sub(1);
+        let column = diagnostic.span.columns
+            .as_ref()
+            .map(|columns| columns.start)
+            .unwrap_or(0);
+
+        let mut msg = diagnostic.message.summary();
+        if let Some(help) = diagnostic.message.help() {
+            msg.push_str("\nHelp: ");
+            msg.push_str(&help);
+        }
+
+        let kind = match diagnostic.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        add_editor_annotation(row, column, msg, kind);
+        
+        for related in diagnostic.related.iter().filter(|related| related.span.location.is_main()) {
+            let row = related.span.location.line.saturating_sub(1);
+            let column = related.span.columns
+                .as_ref()
+                .map(|columns| columns.start)
+                .unwrap_or(0);
+            add_editor_annotation(row, column, related.message.clone(), "info");
+        }
+    }
+    
+    #[allow(unused_mut)]
+    pub fn highlight_line(&mut self, row: Option<usize>) {
+        match row {
+            Some(row) => set_editor_highlight(row.saturating_sub(1)),
+            None => clear_editor_highlight()
+        }
+    }
+    
     #[allow(unused_mut)]
     pub fn clear_errors(&mut self) {
         clear_editor_annotations()
@@ -0,0 +1,18 @@
+use seed::prelude::*;
+
+#[wasm_bindgen]
+extern "C" {
+    fn download_file(name: &str, bytes: &[u8]);
+    fn read_image_file() -> js_sys::Promise;
+}
+
+/// Lets the browser save `bytes` as a file named `name`.
+pub fn download(name: &str, bytes: &[u8]) {
+    download_file(name, bytes)
+}
+
+/// Asks the user for a program image, `None` if no file was chosen or it could not be read.
+pub async fn read_image() -> Option<Vec<u8>> {
+    let bytes = seed::JsFuture::from(read_image_file()).await.ok()?;
+    Some(js_sys::Uint8Array::new(&bytes).to_vec())
+}
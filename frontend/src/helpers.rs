@@ -4,8 +4,9 @@ use std::str::FromStr;
 use seed::prelude::*;
 
 use kasm::{cpu::ExecResult, Error};
+use kasm::source_map::SourceMap;
 
-use crate::{console::ConsoleOut, Msg};
+use crate::{console::ConsoleOut, Model, Msg};
 use crate::settings::Settings;
 
 pub fn parse_from_str_into<T: FromStr + Default>(value: &str, into: &mut T) {
@@ -24,6 +25,7 @@ pub fn handle_step_to_res(
     res: Result<ExecResult, Error>,
     settings: &Settings,
     not_finished_msg: Msg,
+    source_map: &SourceMap,
     console: &ConsoleOut,
     orders: &mut impl Orders<Msg>,
 ) {
@@ -41,8 +43,22 @@ pub fn handle_step_to_res(
         }
         Ok(_) => {}
         Err(err) => {
+            let err = source_map.annotate(err);
             writeln!(console.clone(), "{}", err)
                 .expect("ConsoleOut will never fail");
+
+            // errors in included files can't be shown in the editor
+            if let Some(location) = err.line().filter(|location| location.is_main()) {
+                orders.send_msg(Msg::SetError { line: location.line, msg: err.to_string() });
+            }
         }
     }
 }
+
+pub fn highlight_current_line(model: &mut Model) {
+    let line = model.source_map
+        .line(model.cpu.BZ())
+        .filter(|location| location.is_main())
+        .map(|location| location.line);
+    model.editor.highlight_line(line);
+}
@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+
+use seed::prelude::{*, web_sys::Storage};
+use wasm_bindgen::JsValue;
+
+use kasm::source::InMemory;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_name = localStorage)]
+    static LOCAL_STORAGE: Storage;
+}
+
+/// Files that can be included into the editor code with `.include "<name>"`.
+#[derive(Default, serde::Deserialize, serde::Serialize)]
+pub struct Library {
+    files: BTreeMap<String, String>,
+}
+
+impl Library {
+    pub fn from_storage() -> Result<Self, JsValue> {
+        if let Some(library) = LOCAL_STORAGE.get_item("library")? {
+            let library = serde_json::from_str::<Self>(&library)
+                .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+            return Ok(library);
+        }
+
+        Err(JsValue::NULL)
+    }
+
+    pub fn save_to_storage(&self) -> Result<(), JsValue> {
+        LOCAL_STORAGE.set_item(
+            "library",
+            &serde_json::to_string(self)
+                .expect("Serializing the Library will never fail"),
+        )
+    }
+
+    pub fn insert(&mut self, name: String, source: String) {
+        self.files.insert(name, source);
+        let _ = self.save_to_storage();
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.files.remove(name);
+        let _ = self.save_to_storage();
+    }
+
+    pub fn names(&self) -> impl Iterator<Item=&str> {
+        self.files.keys().map(String::as_str)
+    }
+
+    /// A source provider that resolves includes to the files of the library.
+    pub fn provider(&self) -> InMemory {
+        InMemory::new(self.files.clone().into_iter().collect())
+    }
+}
@@ -6,13 +6,21 @@ use std::num::NonZeroU64;
 use seed::{*, prelude::*};
 
 use console::ConsoleOut;
-use kasm::{cpu::CPU, Error, RAM};
+use kasm::{cpu::{self, CPU}, RAM};
+use kasm::disassembler::Disassembler;
+use kasm::formatter::Formatter;
+use kasm::image::Image;
+use kasm::lexer::{Document, options::Options};
+use kasm::source_map::SourceMap;
 
 use crate::editor::Editor;
+use crate::library::Library;
 use crate::settings::{CpuMode, Settings};
 
 mod console;
 mod editor;
+mod files;
+mod library;
 mod settings;
 
 mod helpers;
@@ -23,9 +31,12 @@ static ALLOC: wee_alloc::WeeAlloc<'_> = wee_alloc::WeeAlloc::INIT;
 
 pub struct Model {
     cpu: CPU<ConsoleOut>,
+    source_map: SourceMap,
     console: ConsoleOut,
     editor: Editor,
     settings: Settings,
+    library: Library,
+    library_file_name: String,
 }
 
 #[derive(Clone)]
@@ -33,6 +44,11 @@ pub enum Msg {
     Run,
     Reset,
     Compile,
+    ExportImage,
+    ImportImage,
+    ImageImported(Vec<u8>),
+    Disassemble,
+    Format,
 
     Step,
     StepToEnd,
@@ -45,9 +61,14 @@ pub enum Msg {
     ToggleShowHelp,
     ToggleShowSettings,
     ToggleContinueAfterMaxSteps,
+    ToggleStrictJumpPoints,
 
     SetEditorFontSize(String),
     SetMaxStepsBetweenRender(String),
+
+    SetLibraryFileName(String),
+    SaveToLibrary,
+    RemoveFromLibrary(String),
     
     ClearConsole,
 
@@ -60,19 +81,46 @@ pub enum Msg {
 
 fn init(_url: Url, _orders: &mut impl Orders<Msg>) -> Model {
     let console = ConsoleOut::default();
-    let cpu = CPU::new(RAM::default(), console.clone());
+    let cpu = CPU::new(RAM::default(), cpu::Config::default(), console.clone());
     let settings = Settings::from_storage().unwrap_or(Settings::default());
 
     Editor.set_font_size(settings.editor_font_size);
 
     Model {
         cpu,
+        source_map: SourceMap::default(),
         console,
         editor: Editor,
         settings,
+        library: Library::from_storage().unwrap_or_default(),
+        library_file_name: String::new(),
     }
 }
 
+/// Assembles the code in the editor and reports all diagnostics.
+fn assemble(model: &mut Model) -> Option<Document> {
+    let code = model.editor.get_code()?;
+    let options = Options {
+        strict_jump_points: model.settings.strict_jump_points,
+    };
+    let (doc, diagnostics) = Document::assemble_with(
+        &code,
+        None,
+        &model.library.provider(),
+        &options,
+    );
+
+    if !diagnostics.is_empty() {
+        writeln!(model.console, "{}", diagnostics.render(&code))
+            .expect("Writing to console will never fail");
+    }
+    for diagnostic in diagnostics.iter() {
+        model.editor.set_diagnostic(diagnostic);
+    }
+
+    doc
+}
+
 fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
     match msg {
         Msg::Run => {
@@ -90,53 +138,96 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
                 .send_msg(Msg::ClearErrors);
         }
         Msg::Compile => {
-            if let Some(ref code) = model.editor.get_code() {
-                match kasm::lexer::Document::from_str(code) {
-                    Ok(doc) => {
-                        *model.cpu.ram_mut() = doc.as_ram();
-                    }
-                    Err(err) => {
-                        writeln!(model.console, "{}", err)
-                            .expect("Writing to console will never fail");
-                        
-                        if let Error::ParsingFailed { line, err, .. } |
-                        Error::InvalidTokenArrangement { line, err } = err {
-                            model.editor.set_error(line, format!("{}", err));
-                        }
-                    }
+            if let Some(doc) = assemble(model) {
+                *model.cpu.ram_mut() = doc.as_ram();
+                *model.cpu.BZ_mut() = doc.entry().unwrap_or(0);
+                model.source_map = doc.source_map();
+            }
+        }
+        Msg::ExportImage => {
+            if let Some(doc) = assemble(model) {
+                files::download("program.kimg", &doc.image().to_bytes());
+            }
+        }
+        Msg::ImportImage => {
+            orders.perform_cmd(async { files::read_image().await.map(Msg::ImageImported) });
+        }
+        Msg::Disassemble => {
+            let source = Disassembler::new(model.cpu.ram())
+                .with_symbols(model.source_map.all_labels().map(|(addr, name)| (name, addr)))
+                .disassemble();
+            match source {
+                Ok(source) => write!(model.console, "{}", source),
+                Err(err) => writeln!(model.console, "{}", err),
+            }.expect("Writing to console will never fail");
+        }
+        Msg::Format => {
+            if let Some(code) = model.editor.get_code() {
+                model.editor.set_code(&Formatter::default().format(&code));
+            }
+        }
+        Msg::ImageImported(bytes) => {
+            match Image::from_bytes(&bytes) {
+                Ok(image) => {
+                    model.cpu.reset_registers();
+                    *model.cpu.ram_mut() = image.ram;
+                    *model.cpu.BZ_mut() = image.entry;
+                    model.source_map = image.source_map.unwrap_or_default();
+                    helpers::highlight_current_line(model);
                 }
+                Err(err) => writeln!(model.console, "{}", err)
+                    .expect("Writing to console will never fail"),
             }
         }
         
-        Msg::Step => helpers::handle_step_to_res(
-            model.cpu.step(),
-            &model.settings,
-            Msg::Step,
-            &model.console,
-            orders
-        ),
-        Msg::StepToEnd => helpers::handle_step_to_res(
-            model.cpu.step_to_end(model.settings.max_steps_between_render), 
-            &model.settings,
-            Msg::StepToEnd,
-            &model.console,
-            orders
-        ),
-        Msg::StepToBreakpoint => helpers::handle_step_to_res(
-            model.cpu.step_to_breakpoint(model.settings.max_steps_between_render),
-            &model.settings,
-            Msg::Step,
-            &model.console,
-            orders,
-        ),
-        Msg::ResetRegisters => model.cpu.reset_registers(),
-        Msg::BZChanged(s) => helpers::parse_from_str_into(&s, model.cpu.BZ_mut()),
+        Msg::Step => {
+            helpers::handle_step_to_res(
+                model.cpu.step(),
+                &model.settings,
+                Msg::Step,
+                &model.source_map,
+                &model.console,
+                orders
+            );
+            helpers::highlight_current_line(model);
+        }
+        Msg::StepToEnd => {
+            helpers::handle_step_to_res(
+                model.cpu.step_to_end(model.settings.max_steps_between_render),
+                &model.settings,
+                Msg::StepToEnd,
+                &model.source_map,
+                &model.console,
+                orders
+            );
+            helpers::highlight_current_line(model);
+        }
+        Msg::StepToBreakpoint => {
+            helpers::handle_step_to_res(
+                model.cpu.step_to_breakpoint(model.settings.max_steps_between_render),
+                &model.settings,
+                Msg::Step,
+                &model.source_map,
+                &model.console,
+                orders,
+            );
+            helpers::highlight_current_line(model);
+        }
+        Msg::ResetRegisters => {
+            model.cpu.reset_registers();
+            helpers::highlight_current_line(model);
+        }
+        Msg::BZChanged(s) => {
+            helpers::parse_from_str_into(&s, model.cpu.BZ_mut());
+            helpers::highlight_current_line(model);
+        }
 
         Msg::ToggleShowInstructionNames => model.settings.toggle_show_instruction_names(),
         Msg::ToggleShowDataRegisters => model.settings.toggle_show_data_registers(),
         Msg::ToggleShowHelp => model.settings.toggle_show_help(),
         Msg::ToggleShowSettings => model.settings.toggle_show_settings(),
         Msg::ToggleContinueAfterMaxSteps => model.settings.toggle_continue_after_max_steps(),
+        Msg::ToggleStrictJumpPoints => model.settings.toggle_strict_jump_points(),
 
         Msg::SetEditorFontSize(s) => {
             helpers::parse_from_str_into(&s, &mut model.settings.editor_font_size);
@@ -148,6 +239,15 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
             let _ = model.settings.save_to_storage();
         }
 
+        Msg::SetLibraryFileName(name) => model.library_file_name = name,
+        Msg::SaveToLibrary => {
+            let name = model.library_file_name.trim();
+            if let (false, Some(code)) = (name.is_empty(), model.editor.get_code()) {
+                model.library.insert(name.to_owned(), code);
+            }
+        }
+        Msg::RemoveFromLibrary(name) => model.library.remove(&name),
+
         Msg::ClearConsole => model.console.clear(),
         Msg::SetError { line, msg } => model.editor.set_error(line, msg),
         Msg::ClearErrors => model.editor.clear_errors()
@@ -159,7 +259,7 @@ fn view(model: &Model) -> Node<Msg> {
         C!["d-flex", "flex-column", "vh-100"],
         
         views::help::view(model),
-        views::settings::view(&model.settings),
+        views::settings::view(&model.settings, &model.library, &model.library_file_name),
 
         views::header::view(),
         views::main::view(&model),
@@ -23,6 +23,9 @@ pub struct Settings {
     pub show_help: bool,
     pub show_settings: bool,
     
+    #[serde(default)]
+    pub strict_jump_points: bool,
+    
     #[serde(default)]
     pub cpu_mode: CpuMode
 }
@@ -68,6 +71,7 @@ impl Settings {
         show_data_registers
         show_help
         show_settings
+        strict_jump_points
     }
 }
 
@@ -82,6 +86,7 @@ impl Default for Settings {
             show_data_registers: true,
             show_help: false,
             show_settings: false,
+            strict_jump_points: false,
             cpu_mode: CpuMode::default()
         }
     }
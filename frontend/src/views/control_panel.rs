@@ -20,6 +20,11 @@ pub fn view() -> Node<Msg> {
                 "Compiles the code in the editor and loads it into RAM",
                 Msg::Compile
             ),
+            view_control_panel_btn(
+                "Format",
+                "Formats the code in the editor",
+                Msg::Format
+            ),
             view_control_panel_btn(
                 "Reset",
                 "Resets all registers to 0 and clears the RAM",
@@ -44,6 +49,25 @@ pub fn view() -> Node<Msg> {
                 "Executes all instructions till the end (END)",
                 Msg::StepToEnd
             ),
+        ],
+        div![
+            C!["row"],
+
+            view_control_panel_btn(
+                "Export image",
+                "Compiles the code and downloads it as a program image (.kimg)",
+                Msg::ExportImage
+            ),
+            view_control_panel_btn(
+                "Import image",
+                "Loads a program image (.kimg) into RAM, without its source code",
+                Msg::ImportImage
+            ),
+            view_control_panel_btn(
+                "Disassemble",
+                "Writes the program in RAM as source code to the console",
+                Msg::Disassemble
+            ),
         ]
     ]
 }
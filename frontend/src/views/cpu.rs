@@ -29,7 +29,8 @@ pub fn view_registers(model: &Model) -> Node<Msg> {
                             C!["text-center"],
                         ]
                     ],
-                ]
+                ],
+                view_register("Flags", None, model.cpu.flags().to_string()),
             ],   
             IF!(
                 model.settings.show_data_registers =>
@@ -9,6 +9,8 @@ pub fn view(model: &Model) -> Node<Msg> {
             
             model.editor.view(),
             crate::views::ram::view(&model.cpu, &model.settings),
+            crate::views::memory::view(&model.cpu),
+            crate::views::stack::view(&model.cpu, &model.source_map),
             div![
                 C!["col-6", "d-flex", "flex-column"],
                 
@@ -0,0 +1,35 @@
+use seed::{*, prelude::*};
+use crate::Msg;
+use crate::console::ConsoleOut;
+use kasm::cpu::CPU;
+
+pub fn view(cpu: &CPU<ConsoleOut>) -> Node<Msg> {
+    div![
+        id!("memory-table"),
+        C!["col", "p-0", "bg-secondary",  "overflow-auto", "position-relative"],
+        
+        table![
+            C!["table", "table-dark", "table-striped", "table-sm", "text-center", "position-absolute"],
+            
+            thead![
+                tr![
+                    th!["M"],
+                    th!["Value"],
+                ]
+            ],
+            tbody![
+                cpu
+                    .memory()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, val)| {
+                        tr![
+                            th![i],
+                            td![val],
+                        ]
+                    })
+                    .collect::<Vec<_>>()
+            ]
+        ]
+    ]
+}
@@ -5,6 +5,8 @@ pub mod footer;
 pub mod header;
 pub mod help;
 pub mod main;
+pub mod memory;
 pub mod popup;
 pub mod ram;
 pub mod settings;
+pub mod stack;
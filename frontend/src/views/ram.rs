@@ -4,7 +4,6 @@ use crate::console::ConsoleOut;
 use kasm::cpu::CPU;
 use kasm::instruction::Instruction;
 use crate::settings::Settings;
-use num_traits::FromPrimitive;
 
 pub fn view(cpu: &CPU<ConsoleOut>, settings: &Settings) -> Node<Msg> {
     div![
@@ -28,18 +27,22 @@ pub fn view(cpu: &CPU<ConsoleOut>, settings: &Settings) -> Node<Msg> {
                         .iter()
                         .enumerate()
                         .map(|(i, (inst, val))| {
+                            let decoded = Instruction::decode(*inst);
                             tr![
                                 th![i],
                                 td![
                                     C![IF!(cpu.BZ() == i as u64 => "table-active")],
-                                    match Instruction::from_u64(*inst) {
-                                        Some(inst) if settings.show_instruction_names => inst.to_string(),
+                                    match decoded {
+                                        Some((inst, _)) if settings.show_instruction_names => inst.to_string(),
                                         _ => inst.to_string()
                                     }
                                 ],
                                 td![
                                     C![IF!(cpu.BZ() == i as u64 => "table-active")],
-                                    val
+                                    match decoded {
+                                        Some((_, addressing)) if settings.show_instruction_names => addressing.format(val),
+                                        _ => val.to_string()
+                                    }
                                 ],
                             ]
                         })
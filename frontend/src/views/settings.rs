@@ -1,9 +1,10 @@
 use crate::Msg;
 use seed::{*, prelude::*};
+use crate::library::Library;
 use crate::settings::Settings;
 
 
-pub fn view(settings: &Settings) -> Node<Msg> {
+pub fn view(settings: &Settings, library: &Library, library_file_name: &str) -> Node<Msg> {
     crate::views::popup::view(
         settings,
         |settings| settings.show_settings,
@@ -54,10 +55,63 @@ pub fn view(settings: &Settings) -> Node<Msg> {
                     Msg::ToggleContinueAfterMaxSteps,
                     settings.continue_after_max_steps
                 ),
+                view_setting_switch(
+                    "strictJumpPoints",
+                    "Reject jump points that only differ in case, like .loop and .LOOP",
+                    "Strict jump points",
+                    Msg::ToggleStrictJumpPoints,
+                    settings.strict_jump_points
+                ),
+                view_library(library, library_file_name),
             ]
     )
 }
 
+fn view_library(library: &Library, file_name: &str) -> Node<Msg> {
+    div![
+        div![
+            C!["border-bottom", "border-secondary", "fs-3", "fw-bold", "text-center", "mt-3"],
+            attrs! {
+                At::Title => "Files that can be included with .include \"<name>\"",
+            },
+            "Library"
+        ],
+        div![
+            C!["input-group", "row", "mx-auto", "my-1"],
+
+            input![
+                C!["form-control", "w-50"],
+                input_ev(Ev::Input, Msg::SetLibraryFileName),
+                attrs! {
+                    At::Placeholder => "File name, like lib.kasm",
+                    At::Value => file_name,
+                },
+            ],
+            button![
+                C!["btn", "btn-outline-secondary", "w-50"],
+                attrs! {
+                    At::Title => "Save the code in the editor as a library file",
+                },
+                ev(Ev::Click, |_| Msg::SaveToLibrary),
+                "Save editor code"
+            ],
+        ],
+        library.names().map(|name| {
+            let remove = name.to_owned();
+            div![
+                C!["input-group", "row", "mx-auto", "my-1"],
+
+                span![C!["input-group-text", "w-50"], format!(".include \"{}\"", name)],
+                button![
+                    C!["btn", "btn-outline-danger", "w-50"],
+                    ev(Ev::Click, move |_| Msg::RemoveFromLibrary(remove)),
+                    "Remove"
+                ],
+            ]
+        }),
+    ]
+}
+
 fn view_setting_switch(id: &str, title: &str, label: &str, msg: Msg, value: bool) -> Node<Msg> {
     div![
         C!["input-group", "row", "mx-auto", "my-1"],
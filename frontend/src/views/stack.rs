@@ -0,0 +1,80 @@
+use seed::{*, prelude::*};
+use crate::Msg;
+use crate::console::ConsoleOut;
+use kasm::cpu::CPU;
+use kasm::source_map::SourceMap;
+use kasm::URS;
+
+pub fn view(cpu: &CPU<ConsoleOut>, source_map: &SourceMap) -> Node<Msg> {
+    // the current subroutine first, followed by the `CALL`s that lead to it
+    let mut frames = cpu
+        .return_addresses()
+        .map(|addr| addr.saturating_sub(1))
+        .collect::<Vec<_>>();
+    frames.push(cpu.BZ());
+    frames.reverse();
+
+    div![
+        id!("stack-table"),
+        C!["col", "p-0", "bg-secondary",  "overflow-auto", "position-relative"],
+        
+        table![
+            C!["table", "table-dark", "table-striped", "table-sm", "text-center", "position-absolute"],
+            
+            thead![
+                tr![
+                    th!["Call stack"],
+                    th!["BZ"],
+                ]
+            ],
+            tbody![
+                frames
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &addr)| {
+                        tr![
+                            td![
+                                C![IF!(i == 0 => "table-active")],
+                                frame_name(source_map, addr)
+                            ],
+                            td![
+                                C![IF!(i == 0 => "table-active")],
+                                addr
+                            ],
+                        ]
+                    })
+                    .collect::<Vec<_>>()
+            ],
+            thead![
+                tr![
+                    th!["#"],
+                    th!["Stack"],
+                ]
+            ],
+            tbody![
+                cpu
+                    .stack()
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .map(|(i, val)| {
+                        tr![
+                            th![i],
+                            td![val],
+                        ]
+                    })
+                    .collect::<Vec<_>>()
+            ]
+        ]
+    ]
+}
+
+/// The jump point a frame is in.
+fn frame_name(source_map: &SourceMap, addr: URS) -> String {
+    source_map
+        .all_labels()
+        .take_while(|&(label, _)| label <= addr)
+        .last()
+        .map(|(_, name)| format!(".{}", name))
+        .unwrap_or_else(|| "main".to_owned())
+}
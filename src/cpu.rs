@@ -4,8 +4,10 @@ use byte_slice_cast::AsByteSlice;
 use num_traits::FromPrimitive;
 
 use crate::{DATA_REGISTERS, Error, IRS, RAM, Result, URS};
-use crate::instruction::Instruction;
+use crate::instruction::{Addressing, Instruction};
 use crate::interrupt::Interrupt;
+use std::convert::TryFrom;
+use strum::EnumString;
 use std::num::NonZeroU64;
 
 #[derive(Debug)]
@@ -13,9 +15,100 @@ pub struct CPU<W> {
     A: IRS,
     BZ: URS,
     Rx: [IRS; DATA_REGISTERS],
+    flags: Flags,
+    memory: Vec<IRS>,
+    stack: Vec<IRS>,
+    /// The positions of the return addresses of all active calls in the stack.
+    calls: Vec<usize>,
 
     ram: RAM,
     stdout: W,
+    config: Config,
+}
+
+/// The parts of the CPU that can be configured.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The number of words in the data memory.
+    pub memory_size: usize,
+    /// The number of values the stack can hold, including the return addresses of `CALL`.
+    pub stack_depth: usize,
+    /// What arithmetic instructions do when the result does not fit into A.
+    pub overflow: Overflow,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            memory_size: 1024,
+            stack_depth: 256,
+            overflow: Overflow::Wrap,
+        }
+    }
+}
+
+/// What arithmetic instructions do when the signed result does not fit into A.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, EnumString, strum::Display, strum::EnumVariantNames)]
+#[strum(serialize_all = "lowercase")]
+pub enum Overflow {
+    /// Wraps around, like `i64::MAX + 1 = i64::MIN`.
+    Wrap,
+    /// Clamps the result to `i64::MIN` or `i64::MAX`.
+    Saturate,
+    /// Stops the program with an error.
+    Trap,
+}
+
+/// The status flags, updated by arithmetic and bitwise instructions.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Flags {
+    /// The unsigned result did not fit, a subtraction borrowed, or the last bit shifted out was 1.
+    pub carry: bool,
+    /// The signed result did not fit.
+    pub overflow: bool,
+    pub zero: bool,
+    pub negative: bool,
+}
+
+impl Flags {
+    /// The flags as bits, carry in bit 0, overflow in bit 1, zero in bit 2 and negative in bit 3.
+    pub fn bits(self) -> URS {
+        self.carry as URS | (self.overflow as URS) << 1 | (self.zero as URS) << 2 | (self.negative as URS) << 3
+    }
+
+    pub fn from_bits(bits: URS) -> Self {
+        Self {
+            carry: bits & 1 != 0,
+            overflow: bits & 1 << 1 != 0,
+            zero: bits & 1 << 2 != 0,
+            negative: bits & 1 << 3 != 0,
+        }
+    }
+}
+
+impl std::fmt::Display for Flags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "C={} V={} Z={} N={}",
+            self.carry as u8, self.overflow as u8, self.zero as u8, self.negative as u8
+        )
+    }
+}
+
+/// The result of an arithmetic instruction, before the overflow behaviour is applied.
+struct Outcome {
+    /// The result, wrapped around on overflow.
+    value: IRS,
+    carry: bool,
+    /// The saturated result, if the signed result did not fit.
+    overflow: Option<IRS>,
+}
+
+impl Outcome {
+    fn new(value: IRS) -> Self {
+        Self { value, carry: false, overflow: None }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -29,13 +122,18 @@ pub enum ExecResult {
 
 impl<W> CPU<W>
     where W: Write {
-    pub fn new(ram: RAM, stdout: W) -> Self {
+    pub fn new(ram: RAM, config: Config, stdout: W) -> Self {
         Self {
             A: 0,
             BZ: 0,
+            flags: Flags::default(),
             Rx: [0; DATA_REGISTERS],
+            memory: vec![0; config.memory_size],
+            stack: Vec::new(),
+            calls: Vec::new(),
             ram,
             stdout,
+            config,
         }
     }
 
@@ -51,6 +149,25 @@ impl<W> CPU<W>
         &self.Rx
     }
 
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    /// The data memory, addressed by `MLOAD` and `MSTORE`.
+    pub fn memory(&self) -> &[IRS] {
+        &self.memory
+    }
+
+    /// The stack, with the top of the stack last.
+    pub fn stack(&self) -> &[IRS] {
+        &self.stack
+    }
+
+    /// The return addresses of all active calls, with the innermost call last.
+    pub fn return_addresses(&self) -> impl Iterator<Item=URS> + '_ {
+        self.calls.iter().map(move |&i| self.stack[i] as URS)
+    }
+
     pub fn ram(&self) -> &RAM {
         &self.ram
     }
@@ -59,18 +176,43 @@ impl<W> CPU<W>
         &self.stdout
     }
     
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+    
+    pub fn A_mut(&mut self) -> &mut IRS {
+        &mut self.A
+    }
+    
     pub fn BZ_mut(&mut self) -> &mut URS {
         &mut self.BZ
     }
     
+    pub fn Rx_mut(&mut self) -> &mut [IRS; DATA_REGISTERS] {
+        &mut self.Rx
+    }
+    
+    pub fn flags_mut(&mut self) -> &mut Flags {
+        &mut self.flags
+    }
+    
+    pub fn memory_mut(&mut self) -> &mut [IRS] {
+        &mut self.memory
+    }
+    
     pub fn ram_mut(&mut self) -> &mut RAM { 
         &mut self.ram
     }
     
+    /// Resets all registers, the flags and the data memory to 0, and empties the stack.
     pub fn reset_registers(&mut self) {
         self.A = 0;
         self.BZ = 0;
+        self.flags = Flags::default();
         self.Rx = [0; DATA_REGISTERS];
+        self.memory.iter_mut().for_each(|word| *word = 0);
+        self.stack.clear();
+        self.calls.clear();
     }
 
     pub fn step_to_breakpoint(&mut self, max_steps: NonZeroU64) -> Result<ExecResult> {
@@ -99,27 +241,27 @@ impl<W> CPU<W>
     }
 
     pub fn step(&mut self) -> Result<ExecResult> {
-        let (inst, value) = self.next_instruction()?;
-        self.exec(inst, value)
+        let (inst, addressing, value) = self.next_instruction()?;
+        self.exec(inst, addressing, value)
     }
 
-    fn next_instruction(&self) -> Result<(Instruction, IRS)> {
+    fn next_instruction(&self) -> Result<(Instruction, Addressing, IRS)> {
         let &(inst, value) = self.ram
             .get(self.BZ as usize)
             .ok_or(Error::NoMoreInstructions { BZ: self.BZ })?;
 
-        let inst = Instruction::from_u64(inst)
+        let (decoded, addressing) = Instruction::decode(inst)
             .ok_or(Error::InvalidInstruction { inst, BZ: self.BZ })?;
 
-        Ok((inst, value))
+        Ok((decoded, addressing, value))
     }
 
-    pub fn exec(&mut self, inst: Instruction, value: IRS) -> Result<ExecResult> {
+    pub fn exec(&mut self, inst: Instruction, addressing: Addressing, value: IRS) -> Result<ExecResult> {
         use Instruction::*;
 
         match inst {
             LOAD => {
-                self.A = self.get_rx(value)?;
+                self.A = self.Rx[self.register(addressing, value)?];
                 self.BZ += 1;
                 Ok(ExecResult::None)
             }
@@ -129,26 +271,38 @@ impl<W> CPU<W>
                 Ok(ExecResult::None)
             }
             STORE => {
-                *self.get_rx_mut(value)? = self.A;
+                self.Rx[self.register(addressing, value)?] = self.A;
                 self.BZ += 1;
                 Ok(ExecResult::None)
             }
-            ADD => self.calc(value, |a, rx| a.wrapping_add(rx)),
-            SUB => self.calc(value, |a, rx| a.wrapping_sub(rx)),
-            MULT => self.calc(value, |a, rx| a.wrapping_mul(rx)),
-            DIV => {
-                if self.get_rx(value)? == 0 {
-                    return Err(Error::DivideByZero { lhs: self.A, BZ: self.BZ });
-                }
-                self.calc(value, |a, rx| a.wrapping_div(rx))
-            }
-            JUMP => self.jump(value, |_| true),
-            JGE => self.jump(value, |a| a >= 0),
-            JGT => self.jump(value, |a| a > 0),
-            JLE => self.jump(value, |a| a <= 0),
-            JLT => self.jump(value, |a| a < 0),
-            JEQ => self.jump(value, |a| a == 0),
-            JNE => self.jump(value, |a| a != 0),
+            ADD | DADD => self.calc(self.operand(inst, addressing, value)?, add),
+            SUB | DSUB => self.calc(self.operand(inst, addressing, value)?, sub),
+            MULT | DMULT => self.calc(self.operand(inst, addressing, value)?, mul),
+            DIV | DDIV => self.divide(self.operand(inst, addressing, value)?, div),
+            MOD | DMOD => self.divide(self.operand(inst, addressing, value)?, |a, b| Outcome::new(a.wrapping_rem(b))),
+            AND | DAND => self.calc(self.operand(inst, addressing, value)?, |a, b| Outcome::new(a & b)),
+            OR | DOR => self.calc(self.operand(inst, addressing, value)?, |a, b| Outcome::new(a | b)),
+            XOR | DXOR => self.calc(self.operand(inst, addressing, value)?, |a, b| Outcome::new(a ^ b)),
+            SHL | DSHL => self.calc(self.operand(inst, addressing, value)?, shift_left),
+            SHR | DSHR => self.calc(self.operand(inst, addressing, value)?, shift_right),
+            SAR | DSAR => self.calc(self.operand(inst, addressing, value)?, shift_right_arithmetic),
+            NOT => self.calc(0, |a, _| Outcome::new(!a)),
+            NEG => self.calc(0, |a, _| sub(0, a)),
+            JUMP => self.jump(value, true),
+            JGE => self.jump(value, self.A >= 0),
+            JGT => self.jump(value, self.A > 0),
+            JLE => self.jump(value, self.A <= 0),
+            JLT => self.jump(value, self.A < 0),
+            JEQ => self.jump(value, self.A == 0),
+            JNE => self.jump(value, self.A != 0),
+            JC => self.jump(value, self.flags.carry),
+            JNC => self.jump(value, !self.flags.carry),
+            JO => self.jump(value, self.flags.overflow),
+            JNO => self.jump(value, !self.flags.overflow),
+            JZ => self.jump(value, self.flags.zero),
+            JNZ => self.jump(value, !self.flags.zero),
+            JN => self.jump(value, self.flags.negative),
+            JNN => self.jump(value, !self.flags.negative),
             END => {
                 self.BZ += 1;
                 Ok(ExecResult::Ended)
@@ -161,19 +315,83 @@ impl<W> CPU<W>
                 self.BZ += 1;
                 Ok(ExecResult::None)
             }
-            INT => self.handle_interrupt(value)
+            INT => self.handle_interrupt(value),
+            MLOAD => {
+                let addr = self.check_memory_address(self.register(addressing, value)?)?;
+                self.A = self.memory[addr];
+                self.BZ += 1;
+                Ok(ExecResult::None)
+            }
+            MSTORE => {
+                let addr = self.check_memory_address(self.register(addressing, value)?)?;
+                self.memory[addr] = self.A;
+                self.BZ += 1;
+                Ok(ExecResult::None)
+            }
+            CALL => {
+                self.push((self.BZ + 1) as IRS)?;
+                self.calls.push(self.stack.len() - 1);
+                self.BZ = value as URS;
+                Ok(ExecResult::None)
+            }
+            RET => {
+                self.BZ = self.pop()? as URS;
+                Ok(ExecResult::None)
+            }
+            PUSH => {
+                self.push(self.Rx[self.register(addressing, value)?])?;
+                self.BZ += 1;
+                Ok(ExecResult::None)
+            }
+            POP => {
+                let i = self.register(addressing, value)?;
+                self.Rx[i] = self.pop()?;
+                self.BZ += 1;
+                Ok(ExecResult::None)
+            }
+        }
+    }
+
+    /// The right operand of an arithmetic instruction, `Rx[n]` or the immediate value `n`.
+    fn operand(&self, inst: Instruction, addressing: Addressing, value: IRS) -> Result<IRS> {
+        if inst.takes_register() {
+            Ok(self.Rx[self.register(addressing, value)?])
+        } else {
+            Ok(value)
         }
     }
 
-    fn calc<F: FnOnce(IRS, IRS) -> IRS>(&mut self, i: IRS, op: F) -> Result<ExecResult> {
-        self.A = op(self.A, self.Rx[self.check_rx_index(i)?]);
+    /// Sets `A = op(A, rhs)` and updates the flags, overflows are handled as configured.
+    fn calc<F: FnOnce(IRS, IRS) -> Outcome>(&mut self, rhs: IRS, op: F) -> Result<ExecResult> {
+        let outcome = op(self.A, rhs);
+        let value = match (outcome.overflow, self.config.overflow) {
+            (None, _) | (Some(_), Overflow::Wrap) => outcome.value,
+            (Some(saturated), Overflow::Saturate) => saturated,
+            (Some(_), Overflow::Trap) => return Err(Error::ArithmeticOverflow { BZ: self.BZ }),
+        };
+
+        self.A = value;
+        self.flags = Flags {
+            carry: outcome.carry,
+            overflow: outcome.overflow.is_some(),
+            zero: value == 0,
+            negative: value < 0,
+        };
         self.BZ += 1;
 
         Ok(ExecResult::None)
     }
 
-    fn jump<F: FnOnce(IRS) -> bool>(&mut self, addr: IRS, cond: F) -> Result<ExecResult> {
-        if cond(self.A) {
+    /// Like `calc`, but fails if `rhs` is 0.
+    fn divide<F: FnOnce(IRS, IRS) -> Outcome>(&mut self, rhs: IRS, op: F) -> Result<ExecResult> {
+        if rhs == 0 {
+            return Err(Error::DivideByZero { lhs: self.A, BZ: self.BZ });
+        }
+        self.calc(rhs, op)
+    }
+
+    fn jump(&mut self, addr: IRS, cond: bool) -> Result<ExecResult> {
+        if cond {
             self.BZ = addr as URS;
         } else {
             self.BZ += 1;
@@ -201,6 +419,8 @@ impl<W> CPU<W>
             DumpBZ => Ok(ExecResult::Print(self.BZ.to_string())),
             DumpRx => Ok(ExecResult::Print(format!("{:?}", self.Rx))),
             DumpRam => Ok(ExecResult::Print(format!("{:?}", self.ram))),
+            DumpMem => Ok(ExecResult::Print(format!("{:?}", shorten_memory_to_last_val(&self.memory)))),
+            DumpStack => Ok(ExecResult::Print(format!("{:?}", self.stack))),
         };
 
         self.BZ += 1;
@@ -212,9 +432,43 @@ impl<W> CPU<W>
         Ok(self.Rx[i])
     }
 
-    fn get_rx_mut(&mut self, i: IRS) -> Result<&mut IRS> {
-        let i = self.check_rx_index(i)?;
-        Ok(&mut self.Rx[i])
+    fn push(&mut self, val: IRS) -> Result<()> {
+        if self.stack.len() >= self.config.stack_depth {
+            return Err(Error::StackOverflow { depth: self.config.stack_depth, BZ: self.BZ });
+        }
+
+        self.stack.push(val);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<IRS> {
+        let val = self.stack.pop().ok_or(Error::StackUnderflow { BZ: self.BZ })?;
+
+        // popping a return address ends the call, no matter whether by `RET` or `POP`
+        while self.calls.last().is_some_and(|&i| i >= self.stack.len()) {
+            self.calls.pop();
+        }
+        Ok(val)
+    }
+
+    /// The index of the data register an instruction with the argument `n` accesses.
+    fn register(&self, addressing: Addressing, n: IRS) -> Result<usize> {
+        let (i, pointer) = match addressing {
+            Addressing::Direct => return self.check_rx_index(n),
+            Addressing::Indirect => (self.get_rx(n)?, n),
+            Addressing::Indexed(m) => (n.saturating_add(self.get_rx(m as IRS)?), m as IRS),
+        };
+
+        if !index_can_be_converted_to_usize(i) || !index_is_in_range(i) {
+            Err(Error::InvalidPointedRxIndex {
+                i,
+                pointer,
+                len: DATA_REGISTERS,
+                BZ: self.BZ,
+            })
+        } else {
+            Ok(i as usize)
+        }
     }
 
     fn check_rx_index(&self, i: IRS) -> Result<usize> {
@@ -229,6 +483,21 @@ impl<W> CPU<W>
         }
     }
 
+    /// Checks the address in `Rx[i]`, and returns it as an index into the data memory.
+    fn check_memory_address(&self, i: usize) -> Result<usize> {
+        let addr = self.Rx[i];
+        if !index_can_be_converted_to_usize(addr) || addr.is_negative() || addr as usize >= self.memory.len() {
+            Err(Error::InvalidMemoryAddress {
+                addr,
+                i: i as IRS,
+                len: self.memory.len(),
+                BZ: self.BZ,
+            })
+        } else {
+            Ok(addr as usize)
+        }
+    }
+
     pub fn println(&mut self, s: &str) -> Result<()> {
         writeln!(self.stdout, "{}", s)?;
         Ok(())
@@ -254,3 +523,80 @@ fn shorten_rx_to_last_val(rx: &[IRS]) -> &[IRS] {
 
     &rx[..=index]
 }
+
+fn shorten_memory_to_last_val(memory: &[IRS]) -> &[IRS] {
+    let len = memory.iter().rposition(|&word| word != 0).map_or(0, |i| i + 1);
+    &memory[..len]
+}
+
+fn add(a: IRS, b: IRS) -> Outcome {
+    Outcome {
+        value: a.wrapping_add(b),
+        carry: (a as URS).overflowing_add(b as URS).1,
+        overflow: a.checked_add(b).is_none().then(|| a.saturating_add(b)),
+    }
+}
+
+fn sub(a: IRS, b: IRS) -> Outcome {
+    Outcome {
+        value: a.wrapping_sub(b),
+        carry: (a as URS) < (b as URS),
+        overflow: a.checked_sub(b).is_none().then(|| a.saturating_sub(b)),
+    }
+}
+
+/// Sets the carry flag together with the overflow flag.
+fn mul(a: IRS, b: IRS) -> Outcome {
+    let overflow = a.checked_mul(b).is_none().then(|| a.saturating_mul(b));
+    Outcome { value: a.wrapping_mul(b), carry: overflow.is_some(), overflow }
+}
+
+/// Only `i64::MIN / -1` overflows, `b` must not be 0.
+fn div(a: IRS, b: IRS) -> Outcome {
+    let overflow = a.checked_div(b).is_none().then_some(IRS::MAX);
+    Outcome { value: a.wrapping_div(b), carry: overflow.is_some(), overflow }
+}
+
+/// Shifts in zeros, all bits are shifted out if `n` is not in `0..64`.
+///
+/// Overflows if `A * 2^n` does not fit.
+fn shift_left(a: IRS, n: IRS) -> Outcome {
+    let value = match u32::try_from(n) {
+        Ok(n) => a.checked_shl(n).unwrap_or(0),
+        Err(_) => 0,
+    };
+    let saturated = if a < 0 { IRS::MIN } else { IRS::MAX };
+
+    Outcome {
+        value,
+        carry: shifted_out(a, n.checked_neg().and_then(|n| n.checked_add(IRS::BITS as IRS)), false),
+        overflow: (shift_right_arithmetic(value, n).value != a).then_some(saturated),
+    }
+}
+
+/// Shifts in zeros, all bits are shifted out if `n` is not in `0..64`.
+fn shift_right(a: IRS, n: IRS) -> Outcome {
+    let value = match u32::try_from(n) {
+        Ok(n) => (a as URS).checked_shr(n).unwrap_or(0) as IRS,
+        Err(_) => 0,
+    };
+    Outcome { value, carry: shifted_out(a, n.checked_sub(1), false), overflow: None }
+}
+
+/// Shifts in the sign bit, only the sign is left if `n` is not in `0..64`.
+fn shift_right_arithmetic(a: IRS, n: IRS) -> Outcome {
+    let sign = a >> (IRS::BITS - 1);
+    let value = match u32::try_from(n) {
+        Ok(n) => a.checked_shr(n).unwrap_or(sign),
+        Err(_) => sign,
+    };
+    Outcome { value, carry: shifted_out(a, n.checked_sub(1), sign != 0 && n != 0), overflow: None }
+}
+
+/// The bit of `a` that was shifted out last, or `outside` if it is not one of the 64 bits of `a`.
+fn shifted_out(a: IRS, bit: Option<IRS>, outside: bool) -> bool {
+    match bit.and_then(|bit| u32::try_from(bit).ok()).filter(|&bit| bit < IRS::BITS) {
+        Some(bit) => (a as URS) >> bit & 1 != 0,
+        None => outside,
+    }
+}
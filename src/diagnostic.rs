@@ -0,0 +1,323 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::Error;
+use crate::error::Warning;
+use crate::source::Location;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, derive_more::Display)]
+pub enum Severity {
+    #[display(fmt = "warning")]
+    Warning,
+    #[display(fmt = "error")]
+    Error,
+}
+
+/// A location in the source code.
+///
+/// Columns are character offsets into the line starting at 0.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub location: Location,
+    pub columns: Option<Range<usize>>,
+}
+
+impl Span {
+    pub fn line(location: Location) -> Self {
+        Self { location, columns: None }
+    }
+
+    pub fn new(location: Location, columns: Range<usize>) -> Self {
+        Self { location, columns: Some(columns) }
+    }
+}
+
+#[derive(Debug, derive_more::Display, derive_more::From)]
+pub enum Message {
+    Error(Error),
+    Warning(Warning),
+}
+
+impl Message {
+    pub fn summary(&self) -> String {
+        match self {
+            Self::Error(err) => err.summary(),
+            Self::Warning(warning) => warning.summary(),
+        }
+    }
+
+    pub fn notes(&self) -> Vec<String> {
+        match self {
+            Self::Error(err) => err.notes(),
+            Self::Warning(warning) => warning.notes(),
+        }
+    }
+
+    pub fn help(&self) -> Option<String> {
+        match self {
+            Self::Error(err) => err.help(),
+            Self::Warning(warning) => warning.help(),
+        }
+    }
+}
+
+/// A secondary location that helps to understand a diagnostic.
+#[derive(Clone, Debug)]
+pub struct Related {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "{}: {}", severity, message)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub span: Span,
+    pub message: Message,
+    pub related: Vec<Related>,
+}
+
+impl Diagnostic {
+    pub fn error(span: Span, error: Error) -> Self {
+        Self { severity: Severity::Error, span, message: Message::Error(error), related: Vec::new() }
+    }
+
+    pub fn warning(span: Span, warning: Warning) -> Self {
+        Self { severity: Severity::Warning, span, message: Message::Warning(warning), related: Vec::new() }
+    }
+
+    pub fn with_related(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.related.push(Related { span, message: message.into() });
+        self
+    }
+
+    /// Renders the diagnostic together with the source line it points to.
+    ///
+    /// ```text
+    /// error: Failed to parse the unknown token `foo`
+    ///  --> line 1, column 1
+    ///   |
+    /// 1 | foo 1
+    ///   | ^^^
+    ///   |
+    ///   = help: Valid tokens are ...
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        self.render_sources(&Sources { main: source, included: None })
+    }
+
+    fn render_sources(&self, sources: &Sources) -> String {
+        let width = std::iter::once(&self.span)
+            .chain(self.related.iter().map(|r| &r.span))
+            .map(|span| span.location.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let gutter = " ".repeat(width);
+        let mut out = format!("{}: {}\n", self.severity, self.message.summary());
+
+        let file = match self.span.location.file {
+            Some(ref file) => format!("{}, ", file),
+            None => String::new(),
+        };
+        match self.span.columns {
+            Some(ref columns) => out.push_str(&format!(
+                "{}--> {}line {}, column {}\n",
+                gutter, file, self.span.location.line, columns.start + 1
+            )),
+            None => out.push_str(&format!("{}--> {}line {}\n", gutter, file, self.span.location.line)),
+        }
+
+        let file = self.span.location.file.as_deref();
+        render_snippet(&mut out, sources, file, &self.span, width, '^', "");
+        for related in self.related.iter() {
+            render_snippet(&mut out, sources, file, &related.span, width, '-', &related.message);
+        }
+
+        let notes = self.message.notes();
+        let help = self.message.help();
+        if !notes.is_empty() || help.is_some() {
+            out.push_str(&format!("{} |\n", gutter));
+        }
+        for note in notes {
+            out.push_str(&format!("{} = note: {}\n", gutter, note));
+        }
+        if let Some(help) = help {
+            out.push_str(&format!("{} = help: {}\n", gutter, help));
+        }
+
+        out
+    }
+}
+
+/// The source code of all files a diagnostic can point to.
+struct Sources<'a> {
+    main: &'a str,
+    included: Option<&'a BTreeMap<String, String>>,
+}
+
+impl<'a> Sources<'a> {
+    fn get(&self, location: &Location) -> Option<&'a str> {
+        match location.file {
+            Some(ref file) => self.included?.get(&**file).map(String::as_str),
+            None => Some(self.main),
+        }
+    }
+}
+
+fn render_snippet(
+    out: &mut String,
+    sources: &Sources,
+    file: Option<&str>,
+    span: &Span,
+    width: usize,
+    marker: char,
+    label: &str,
+) {
+    let gutter = " ".repeat(width);
+    let line = match sources.get(&span.location).and_then(|s| s.lines().nth(span.location.line.wrapping_sub(1))) {
+        Some(line) => line.replace('\t', " "),
+        None => return,
+    };
+
+    out.push_str(&format!("{} |\n", gutter));
+    // related spans may point into other files than the diagnostic itself
+    if span.location.file.as_deref() != file {
+        out.push_str(&format!("{}::: {}\n", gutter, span.location.file.as_deref().unwrap_or("<main>")));
+    }
+    out.push_str(&format!("{:>width$} | {}\n", span.location.line, line.trim_end(), width = width));
+
+    let columns = match span.columns {
+        Some(ref columns) => columns.clone(),
+        None if !label.is_empty() => {
+            let indent = line.len() - line.trim_start().len();
+            indent..line.trim_end().len()
+        }
+        None => return,
+    };
+    let len = columns.end.saturating_sub(columns.start).max(1);
+    let markers = marker.to_string().repeat(len);
+    let line = format!("{} | {}{} {}", gutter, " ".repeat(columns.start), markers, label);
+    out.push_str(line.trim_end());
+    out.push('\n');
+}
+
+/// A collection of all problems found while assembling a document.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+    /// The source code of all included files, to render snippets from them.
+    sources: BTreeMap<String, String>,
+}
+
+impl Diagnostics {
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn push_error(&mut self, span: Span, error: Error) {
+        self.push(Diagnostic::error(span, error));
+    }
+
+    pub fn push_warning(&mut self, span: Span, warning: Warning) {
+        self.push(Diagnostic::warning(span, warning));
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|d| d.severity == Severity::Error)
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item=&Diagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.diagnostics.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    /// Remembers the source code of an included file.
+    pub fn add_source(&mut self, file: impl Into<String>, source: impl Into<String>) {
+        self.sources.insert(file.into(), source.into());
+    }
+
+    /// The source code of an included file.
+    pub fn source(&self, file: &str) -> Option<&str> {
+        self.sources.get(file).map(String::as_str)
+    }
+
+    /// All included files together with their source code.
+    pub fn sources(&self) -> impl Iterator<Item=(&str, &str)> {
+        self.sources
+            .iter()
+            .map(|(file, source)| (file.as_str(), source.as_str()))
+    }
+
+    /// Renders all diagnostics together with the source lines they point to.
+    ///
+    /// `source` is the source code of the main file.
+    pub fn render(&self, source: &str) -> String {
+        let sources = Sources { main: source, included: Some(&self.sources) };
+        let mut out = self.diagnostics
+            .iter()
+            .map(|d| d.render_sources(&sources))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let errors = self.errors().count();
+        if errors > 0 {
+            out.push_str(&format!("\nFound {} error{}\n", errors, if errors == 1 { "" } else { "s" }));
+        }
+        out
+    }
+
+    /// Orders all diagnostics by their position in the source code.
+    pub fn sort(&mut self) {
+        self.diagnostics.sort_by_key(|d| (d.span.location.clone(), d.span.columns.as_ref().map(|c| c.start)));
+    }
+}
+
+impl std::fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, diagnostic) in self.diagnostics.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+
+        let errors = self.errors().count();
+        if errors > 0 {
+            write!(f, "\nFound {} error{}", errors, if errors == 1 { "" } else { "s" })?;
+        }
+        Ok(())
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.diagnostics.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = std::slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.diagnostics.iter()
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+
+use num_traits::FromPrimitive;
+
+use crate::{Error, IRS, RAM, Result, URS};
+use crate::instruction::Instruction;
+use crate::interrupt::Interrupt;
+use crate::lexer::jump_point::JumpPoint;
+
+/// The column comments are aligned to.
+const COMMENT_COLUMN: usize = 20;
+
+/// Turns a [`RAM`] back into source code, that assembles to the same [`RAM`].
+///
+/// Every jump point declaration assembles to a `NOOP`, so jump points are only declared
+/// where the RAM contains a `NOOP`. Jumps to any other address keep their numeric argument.
+pub struct Disassembler<'a> {
+    ram: &'a RAM,
+    symbols: BTreeMap<URS, String>,
+    entry: Option<URS>,
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(ram: &'a RAM) -> Self {
+        Self { ram, symbols: BTreeMap::new(), entry: None }
+    }
+
+    /// Names jump points after `symbols` instead of their address.
+    ///
+    /// Symbols that don't point to a `NOOP` or aren't valid jump point names,
+    /// like the local jump points of macros, are ignored.
+    pub fn with_symbols<'s>(mut self, symbols: impl IntoIterator<Item=(&'s str, URS)>) -> Self {
+        for (name, addr) in symbols {
+            if self.is_noop(addr) && format!(".{}", name).parse::<JumpPoint>().is_ok() {
+                self.symbols.entry(addr).or_insert_with(|| name.to_owned());
+            }
+        }
+        self
+    }
+
+    /// Starts the program at `entry` using `.entry`.
+    ///
+    /// No source code starts at an instruction other than a `NOOP`,
+    /// so such entry points are only mentioned in a comment.
+    pub fn with_entry(mut self, entry: URS) -> Self {
+        self.entry = Some(entry).filter(|&entry| entry != 0);
+        self
+    }
+
+    pub fn disassemble(&self) -> Result<String> {
+        let labels = self.labels();
+        let mut out = String::new();
+        let mut indent = "";
+
+        match self.entry {
+            Some(entry) if self.is_noop(entry) => writeln!(out, ".entry .{}", labels[&entry]),
+            Some(entry) => writeln!(out, "; the program starts at address {}", entry),
+            None => Ok(()),
+        }.expect("Writing to a string will never fail");
+
+        for (addr, &(code, val)) in self.ram.iter().enumerate() {
+            if let Some(name) = labels.get(&(addr as URS)) {
+                writeln!(out, ".{}:", name).expect("Writing to a string will never fail");
+                indent = "    ";
+                continue;
+            }
+
+            let (line, comment) = match Instruction::decode(code) {
+                Some((inst, _)) if !inst.takes_value() && val != 0 => {
+                    return Err(Error::NotDisassemblable { inst, val, address: addr as URS });
+                }
+                Some((inst, _)) if !inst.takes_value() => (mnemonic(inst), None),
+                Some((inst, addressing)) => {
+                    let argument = match labels.get(&(val as URS)) {
+                        Some(name) if inst.is_jump() && val >= 0 => format!(".{}", name),
+                        _ => addressing.format(val),
+                    };
+                    let comment = match inst {
+                        Instruction::INT => Interrupt::from_i64(val).map(|int| int.to_string()),
+                        _ => None,
+                    };
+                    (format!("{} {}", mnemonic(inst), argument), comment)
+                }
+                // decimal literals may not use the sign bit
+                None if code > IRS::MAX as URS => (format!("{:#x} {}", code, val), Some("unknown instruction".to_owned())),
+                None => (format!("{} {}", code, val), Some("unknown instruction".to_owned())),
+            };
+
+            let line = format!("{}{}", indent, line);
+            match comment {
+                Some(comment) => writeln!(out, "{:<width$}; {}", line, comment, width = COMMENT_COLUMN),
+                None => writeln!(out, "{}", line),
+            }.expect("Writing to a string will never fail");
+        }
+
+        Ok(out)
+    }
+
+    /// The names of all jump points, synthesized for jump targets without a symbol.
+    fn labels(&self) -> BTreeMap<URS, String> {
+        let mut labels = self.symbols.clone();
+        let mut names = labels.values().cloned().collect::<HashSet<_>>();
+
+        let targets = self.ram
+            .iter()
+            .filter(|&&(code, _)| Instruction::decode(code).is_some_and(|(inst, _)| inst.is_jump()))
+            .filter(|&&(_, val)| val >= 0 && self.is_noop(val as URS))
+            .map(|&(_, val)| val as URS)
+            .chain(self.entry.filter(|&entry| self.is_noop(entry)))
+            .collect::<Vec<_>>();
+
+        for addr in targets {
+            if labels.contains_key(&addr) {
+                continue;
+            }
+
+            let mut name = format!("label_{}", addr);
+            while names.contains(&name) {
+                name.push('_');
+            }
+            names.insert(name.clone());
+            labels.insert(addr, name);
+        }
+
+        labels
+    }
+
+    fn is_noop(&self, addr: URS) -> bool {
+        matches!(self.ram.get(addr as usize), Some(&(code, 0)) if code == Instruction::NOOP as URS)
+    }
+}
+
+fn mnemonic(inst: Instruction) -> String {
+    inst.to_string().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::lexer::Document;
+    use crate::lexer::options::Options;
+    use crate::source::{FileSystem, SourceProvider};
+
+    use super::*;
+
+    fn assemble(source: &str) -> Document {
+        let (doc, diagnostics) = Document::from_str_with_diagnostics(source);
+        doc.unwrap_or_else(|| panic!("{}", diagnostics.render(source)))
+    }
+
+    /// Disassembles the program with and without its symbols, and checks that both assemble to the same RAM.
+    fn assert_round_trip(doc: &Document) -> String {
+        let ram = doc.as_ram();
+
+        let plain = Disassembler::new(&ram).disassemble().unwrap();
+        assert_eq!(assemble(&plain).as_ram(), ram, "disassembled without symbols:\n{}", plain);
+
+        let image = doc.image();
+        let named = image.disassemble().unwrap();
+        let reassembled = assemble(&named);
+        assert_eq!(reassembled.as_ram(), ram, "disassembled with symbols:\n{}", named);
+        assert_eq!(reassembled.entry(), doc.entry().filter(|&entry| entry != 0));
+        named
+    }
+
+    #[test]
+    fn examples() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+        let provider = FileSystem::new(&dir);
+
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|extension| extension != "kasm") {
+                continue;
+            }
+
+            let source = std::fs::read_to_string(&path).unwrap();
+            let file = provider.resolve(&path.file_name().unwrap().to_string_lossy(), None);
+            let (doc, diagnostics) = Document::assemble_with(&source, Some(&file), &provider, &Options::default());
+            let doc = doc.unwrap_or_else(|| panic!("{}: {}", path.display(), diagnostics.render(&source)));
+            assert_round_trip(&doc);
+        }
+    }
+
+    #[test]
+    fn jump_to_instruction() {
+        let source = assert_round_trip(&assemble("dload 1\njump 3\nint 2\nend\n"));
+        assert!(source.contains("jump 3"), "{}", source);
+    }
+
+    #[test]
+    fn macro_local_jump_point() {
+        let doc = assemble("\
+.macro countdown reg
+.again:
+    load reg
+    sub 1
+    store reg
+    jgt .again
+.endm
+dload 3
+store 0
+countdown 0
+countdown 0
+end
+");
+        let source = assert_round_trip(&doc);
+        assert!(!source.contains('@'), "{}", source);
+    }
+
+    #[test]
+    fn indexed_and_indirect_arguments() {
+        let source = assert_round_trip(&assemble("dload 1\nstore 0\nload [0]\nstore 2[0]\nadd 2[0]\nint 2\nend\n"));
+        assert!(source.contains("load [0]"), "{}", source);
+        assert!(source.contains("store 2[0]"), "{}", source);
+        assert!(source.contains("add 2[0]"), "{}", source);
+    }
+
+    #[test]
+    fn entry_point() {
+        let source = assert_round_trip(&assemble(".entry .main\n.data:\nret\n.main:\ncall .data\nend\n"));
+        assert!(source.starts_with(".entry .main\n"), "{}", source);
+    }
+}
@@ -1,6 +1,9 @@
 use thiserror::Error;
 
-use crate::{IRS, URS};
+use crate::{DATA_REGISTERS, IRS, URS};
+use crate::diagnostic::Diagnostics;
+use crate::instruction::Instruction;
+use crate::source::Location;
 use crate::lexer::code_token::CodeToken;
 
 #[derive(Error, Debug)]
@@ -24,10 +27,43 @@ pub enum Error {
     )]
     InvalidRxIndex { i: IRS, len: usize, BZ: URS },
     #[error(
+    "Attempted to access Rx[{i}] through the pointer in Rx[{pointer}] at BZ={BZ}\n\
+    Note: Rx has len {len}, and indexing starts at 0\n\
+    Note: Indexes must be positive"
+    )]
+    InvalidPointedRxIndex { i: IRS, pointer: IRS, len: usize, BZ: URS },
+    #[error(
+    "Attempted to access M[{addr}] through Rx[{i}] at BZ={BZ}\n\
+    Note: The data memory has {len} words, and addresses start at 0\n\
+    Note: Addresses must be positive"
+    )]
+    InvalidMemoryAddress { addr: IRS, i: IRS, len: usize, BZ: URS },
+    #[error(
+    "The stack overflowed at BZ={BZ}\n\
+    Note: The stack can hold {depth} values, including the return addresses of `CALL`\n\
+    Note: You probably entered an endless recursion"
+    )]
+    StackOverflow { depth: usize, BZ: URS },
+    #[error(
+    "Attempted to pop from the empty stack at BZ={BZ}\n\
+    Note: Every `POP` needs a `PUSH` before, and every `RET` a `CALL`"
+    )]
+    StackUnderflow { BZ: URS },
+    #[error(
+    "The result of the arithmetic at BZ={BZ} does not fit into A\n\
+    Note: The CPU is configured to stop on overflows, instead of wrapping around or saturating"
+    )]
+    ArithmeticOverflow { BZ: URS },
+    #[error(
     "There are no more instructions at BZ={BZ}\n\
     Note: Always end your program with an `END` instruction"
     )]
     NoMoreInstructions { BZ: URS },
+    #[error(
+    "{err}\n\
+    Note: The instruction at BZ={BZ} was assembled from line {line}"
+    )]
+    InLine { line: Location, BZ: URS, err: Box<Error> },
 
     #[error(
     "The CPU made {0} steps in a row without a break\n\
@@ -37,26 +73,397 @@ pub enum Error {
     TooManySteps(u64),
 
     #[error("The jump point {name} in line {line} is not defined in the document")]
-    UndefinedJumpPoint { name: String, line: usize },
+    UndefinedJumpPoint { name: String, line: Location },
     #[error(
     "Failed to parse `{s}` in line {line}\n\
     Details: {err}"
     )]
-    ParsingFailed { s: String, line: usize, err: ParseError },
+    ParsingFailed { s: String, line: Location, err: ParseError },
     #[error(
     "The provided combination of tokens in line {line} is invalid\n\
     Details: {err}"
     )]
-    InvalidTokenArrangement { line: usize, err: ParseError },
+    InvalidTokenArrangement { line: Location, err: ParseError },
+    #[error(
+    "The string in line {line} does not fit into Rx\n\
+    Note: The string has {len} characters, but there are only {available} registers starting at Rx[{index}]"
+    )]
+    StringDoesNotFit { line: Location, index: URS, len: usize, available: usize },
+    #[error("The constant {name} in line {line} is not defined in the document")]
+    UndefinedConstant { name: String, line: Location },
+    #[error(
+    "The constant {name} in line {line} depends on itself\n\
+    Note: The cycle is {cycle}"
+    )]
+    CyclicConstant { name: String, line: Location, cycle: String },
+    #[error("The constant {name} in line {line} was already defined in line {first_line}")]
+    DuplicateConstant { name: String, line: Location, first_line: Location },
+    #[error(
+    "Failed to evaluate `{expr}` in line {line}\n\
+    Details: {err}"
+    )]
+    ExpressionFailed { expr: String, line: Location, err: ExpressionError },
+    #[error(
+    "The jump point .{name} in line {line} was already declared in line {first_line}\n\
+    Note: Every jump point may only be declared once"
+    )]
+    DuplicateJumpPoint { name: String, line: Location, first_line: Location },
+    #[error(
+    "The jump point .{name} in line {line} only differs in case from .{other} in line {other_line}\n\
+    Note: Jump points that only differ in case are not allowed in strict mode"
+    )]
+    JumpPointDiffersOnlyInCase { name: String, line: Location, other: String, other_line: Location },
+    #[error("The macro {name} in line {line} is never closed")]
+    UnclosedMacro { name: String, line: Location },
+    #[error("The `.endm` in line {line} does not close a macro")]
+    UnexpectedEndMacro { line: Location },
+    #[error(
+    "The macro definition in line {line} is inside of the macro {outer}\n\
+    Note: Macros may not be defined inside of other macros"
+    )]
+    NestedMacro { line: Location, outer: String },
+    #[error("The macro {name} in line {line} was already defined in line {first_line}")]
+    DuplicateMacro { name: String, line: Location, first_line: Location },
+    #[error("The macro {name} in line {line} takes {expected} arguments, but {found} were supplied")]
+    MacroArgumentCount { name: String, line: Location, expected: usize, found: usize },
+    #[error(
+    "The expansion of the macro {name} in line {line} is nested too deeply\n\
+    Note: Macros may only be nested {limit} levels deep"
+    )]
+    MacroRecursionLimit { name: String, line: Location, limit: usize },
+    #[error("The jump point .{name} in line {line} is imported, but also declared in the document")]
+    ImportedJumpPointDeclared { name: String, line: Location },
+    #[error("Failed to include `{path}` in line {line}: {err}")]
+    IncludeFailed { path: String, line: Location, err: std::io::Error },
+    #[error(
+    "The file `{path}` included in line {line} includes itself\n\
+    Note: The cycle is {cycle}"
+    )]
+    IncludeCycle { path: String, line: Location, cycle: String },
+    #[error("{0}")]
+    Assembly(Diagnostics),
+    #[error(
+    "The object in line {line} is malformed\n\
+    Details: {reason}"
+    )]
+    InvalidObject { line: usize, reason: String },
+    #[error(
+    "The expression `{expr}` in line {line} can not be relocated\n\
+    Note: Objects can only relocate a single jump point plus or minus a constant, like `.loop+1`"
+    )]
+    NotRelocatable { expr: String, line: Location },
+    #[error(
+    "The entry point in line {line} was already set in line {first_line}\n\
+    Note: A program can only start at one jump point"
+    )]
+    DuplicateEntry { line: Location, first_line: Location },
+    #[error("{}", LinkError::render_all(.0))]
+    Linking(Vec<LinkError>),
+    #[error("The program image is invalid: {0}")]
+    InvalidImage(ImageError),
+    #[error(
+    "The instruction {inst} {val} at address {address} cannot be disassembled\n\
+    Note: {inst} does not take an argument, so no source code assembles to it"
+    )]
+    NotDisassemblable { inst: Instruction, val: IRS, address: URS },
+    #[error(
+    "The source code is not formatted, starting in line {line}\n\
+    Note: Execute `kasm fmt <file>` to format it"
+    )]
+    NotFormatted { line: Location },
 
     #[error(transparent)]
     IO(#[from] std::io::Error),
 }
 
+impl Error {
+    /// The value of BZ at which a runtime error occurred.
+    pub fn BZ(&self) -> Option<URS> {
+        match *self {
+            Self::InvalidInstruction { BZ, .. } |
+            Self::InvalidInterrupt { BZ, .. } |
+            Self::DivideByZero { BZ, .. } |
+            Self::InvalidRxIndex { BZ, .. } |
+            Self::InvalidPointedRxIndex { BZ, .. } |
+            Self::InvalidMemoryAddress { BZ, .. } |
+            Self::StackOverflow { BZ, .. } |
+            Self::StackUnderflow { BZ } |
+            Self::ArithmeticOverflow { BZ } |
+            Self::NoMoreInstructions { BZ } |
+            Self::InLine { BZ, .. } => Some(BZ),
+            _ => None
+        }
+    }
+
+    /// The source line an error refers to.
+    pub fn line(&self) -> Option<&Location> {
+        match self {
+            Self::InLine { line, .. } |
+            Self::UndefinedJumpPoint { line, .. } |
+            Self::ParsingFailed { line, .. } |
+            Self::InvalidTokenArrangement { line, .. } |
+            Self::StringDoesNotFit { line, .. } |
+            Self::UndefinedConstant { line, .. } |
+            Self::CyclicConstant { line, .. } |
+            Self::DuplicateConstant { line, .. } |
+            Self::ExpressionFailed { line, .. } |
+            Self::DuplicateJumpPoint { line, .. } |
+            Self::JumpPointDiffersOnlyInCase { line, .. } |
+            Self::UnclosedMacro { line, .. } |
+            Self::UnexpectedEndMacro { line } |
+            Self::NestedMacro { line, .. } |
+            Self::DuplicateMacro { line, .. } |
+            Self::MacroArgumentCount { line, .. } |
+            Self::MacroRecursionLimit { line, .. } |
+            Self::ImportedJumpPointDeclared { line, .. } |
+            Self::NotRelocatable { line, .. } |
+            Self::DuplicateEntry { line, .. } |
+            Self::IncludeFailed { line, .. } |
+            Self::IncludeCycle { line, .. } |
+            Self::NotFormatted { line } => Some(line),
+            Self::Assembly(diagnostics) => diagnostics
+                .errors()
+                .next()
+                .map(|d| &d.span.location),
+            _ => None
+        }
+    }
+}
+
+impl Error {
+    /// A short, single line description of the error.
+    pub fn summary(&self) -> String {
+        match self {
+            Self::ParsingFailed { err, .. } |
+            Self::InvalidTokenArrangement { err, .. } => err
+                .to_string()
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_owned(),
+            Self::UndefinedJumpPoint { name, .. } => {
+                format!("The jump point `.{}` is not defined in the document", name)
+            }
+            Self::DuplicateJumpPoint { name, first_line, .. } => {
+                format!("The jump point `.{}` was already declared in line {}", name, first_line)
+            }
+            Self::UndefinedConstant { name, .. } => {
+                format!("The constant `{}` is not defined in the document", name)
+            }
+            Self::CyclicConstant { name, .. } => format!("The constant `{}` depends on itself", name),
+            Self::DuplicateConstant { name, first_line, .. } => {
+                format!("The constant `{}` was already defined in line {}", name, first_line)
+            }
+            Self::ExpressionFailed { expr, err, .. } => format!("Failed to evaluate `{}`: {}", expr, err),
+            Self::JumpPointDiffersOnlyInCase { name, other, other_line, .. } => format!(
+                "The jump point `.{}` only differs in case from `.{}` in line {}",
+                name, other, other_line
+            ),
+            Self::UnclosedMacro { name, .. } => format!("The macro `{}` is never closed", name),
+            Self::NestedMacro { outer, .. } => format!("The macro definition is inside of the macro `{}`", outer),
+            Self::DuplicateMacro { name, first_line, .. } => {
+                format!("The macro `{}` was already defined in line {}", name, first_line)
+            }
+            Self::MacroArgumentCount { name, expected, found, .. } => format!(
+                "The macro `{}` takes {} arguments, but {} were supplied",
+                name, expected, found
+            ),
+            Self::MacroRecursionLimit { name, .. } => {
+                format!("The expansion of the macro `{}` is nested too deeply", name)
+            }
+            Self::ImportedJumpPointDeclared { name, .. } => {
+                format!("The jump point `.{}` is imported, but also declared in the document", name)
+            }
+            Self::DuplicateEntry { first_line, .. } => {
+                format!("The entry point was already set in line {}", first_line)
+            }
+            Self::IncludeFailed { path, err, .. } => format!("Failed to include `{}`: {}", path, err),
+            Self::IncludeCycle { path, .. } => format!("The file `{}` includes itself", path),
+            Self::InLine { err, .. } => err.summary(),
+            err => err
+                .to_string()
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_owned(),
+        }
+    }
+
+    /// All notes that are part of the error message.
+    pub fn notes(&self) -> Vec<String> {
+        match self {
+            Self::InLine { err, .. } => err.notes(),
+            err => err
+                .to_string()
+                .lines()
+                .filter_map(|line| line.trim().strip_prefix("Note: "))
+                .map(str::to_owned)
+                .collect(),
+        }
+    }
+
+    /// A hint on how to fix the error.
+    pub fn help(&self) -> Option<String> {
+        match self {
+            Self::ParsingFailed { err, .. } |
+            Self::InvalidTokenArrangement { err, .. } => err.help(),
+            Self::UndefinedJumpPoint { name, .. } => {
+                Some(format!("Declare the jump point in its own line using `.{}:`", name))
+            }
+            Self::StringDoesNotFit { .. } => {
+                Some("Split the string, or store it at a lower index".to_owned())
+            }
+            Self::DuplicateJumpPoint { .. } => Some("Remove or rename one of the declarations".to_owned()),
+            Self::UndefinedConstant { name, .. } => {
+                Some(format!("Define the constant in its own line using `.equ {} <value>`", name))
+            }
+            Self::DuplicateConstant { .. } => Some("Remove or rename one of the definitions".to_owned()),
+            Self::JumpPointDiffersOnlyInCase { other, .. } => {
+                Some(format!("Rename the jump point or use `.{}` instead", other))
+            }
+            Self::UnclosedMacro { .. } => Some("Add `.endm` after the last line of the macro".to_owned()),
+            Self::UnexpectedEndMacro { .. } => {
+                Some("Remove the `.endm`, or start a macro with `.macro <name>`".to_owned())
+            }
+            Self::NestedMacro { outer, .. } => Some(format!("Close the macro `{}` with `.endm` first", outer)),
+            Self::DuplicateMacro { .. } => Some("Remove or rename one of the definitions".to_owned()),
+            Self::MacroRecursionLimit { .. } => Some("Check whether the macro calls itself endlessly".to_owned()),
+            Self::IncludeCycle { .. } => Some("Remove one of the includes".to_owned()),
+            Self::ImportedJumpPointDeclared { .. } => Some("Remove the import or the declaration".to_owned()),
+            Self::DuplicateEntry { .. } => Some("Remove one of the `.entry` directives".to_owned()),
+            Self::InLine { err, .. } => err.help(),
+            _ => None
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Warning {
+    #[error("The jump point .{name} in line {line} is never used")]
+    UnusedJumpPoint { name: String, line: Location },
+    #[error(
+    "The jump point .{name} in line {line} is declared directly before `END`\n\
+    Note: Jumping to .{name} ends the program"
+    )]
+    JumpPointBeforeEnd { name: String, line: Location },
+}
+
+impl Warning {
+    /// A short, single line description of the warning.
+    pub fn summary(&self) -> String {
+        match self {
+            Self::UnusedJumpPoint { name, .. } => format!("The jump point `.{}` is never used", name),
+            Self::JumpPointBeforeEnd { name, .. } => {
+                format!("The jump point `.{}` is declared directly before `END`", name)
+            }
+        }
+    }
+
+    /// All notes that are part of the warning message.
+    pub fn notes(&self) -> Vec<String> {
+        self
+            .to_string()
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("Note: "))
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// A hint on how to fix the warning.
+    pub fn help(&self) -> Option<String> {
+        match self {
+            Self::UnusedJumpPoint { .. } => Some("Remove the declaration".to_owned()),
+            Self::JumpPointBeforeEnd { .. } => None,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum LinkError {
+    #[error("The jump point .{name} imported by {object} is not exported by any object")]
+    UnresolvedSymbol { name: String, object: String },
+    #[error("The jump point .{name} exported by {object} was already exported by {first_object}")]
+    DuplicateSymbol { name: String, object: String, first_object: String },
+    #[error("The entry point of {object} conflicts with the entry point of {first_object}")]
+    DuplicateEntry { object: String, first_object: String },
+}
+
+impl LinkError {
+    fn render_all(errors: &[Self]) -> String {
+        let mut out = errors
+            .iter()
+            .map(|err| format!("error: {}", err))
+            .collect::<Vec<_>>()
+            .join("\n");
+        out.push_str(&format!("\nFound {} error{}", errors.len(), if errors.len() == 1 { "" } else { "s" }));
+        out
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ImageError {
+    #[error("The file is not a kasm program image")]
+    InvalidMagic,
+    #[error("The image version {version} is not supported")]
+    UnsupportedVersion { version: u16 },
+    #[error("The image uses unknown flags {flags:#06x}")]
+    UnknownFlags { flags: u16 },
+    #[error("The image ends in the middle of the {section} section")]
+    Truncated { section: &'static str },
+    #[error("The instruction code `{opcode}` at address {address} is not a valid instruction")]
+    UnknownOpcode { address: URS, opcode: URS },
+    #[error("The entry point {entry} is outside of the {len} words of code")]
+    InvalidEntryPoint { entry: URS, len: URS },
+    #[error("The symbol .{name} points to the address {address}, that is outside of the code")]
+    InvalidAddress { name: String, address: URS },
+    #[error("The source map refers to the unknown file {index}")]
+    InvalidFileIndex { index: u32 },
+    #[error("A string in the {section} section is not valid UTF-8")]
+    InvalidString { section: &'static str },
+    #[error("The image has {len} unexpected bytes at its end")]
+    TrailingBytes { len: usize },
+}
+
+#[derive(Error, Debug)]
+pub enum ExpressionError {
+    #[error("The constant `{name}` is not defined")]
+    UndefinedConstant { name: String },
+    #[error("The jump point `.{name}` is not defined")]
+    UndefinedJumpPoint { name: String },
+    #[error("The result does not fit into a register")]
+    Overflow,
+    #[error("Attempted to divide by zero")]
+    DivideByZero,
+}
+
 #[derive(Error, Debug)]
 pub enum ParseError {
     #[error("Failed to parse the unknown token `{token}`")]
     UnknownToken { token: String },
+    #[error("The literal `{literal}` is malformed")]
+    InvalidLiteral { literal: String },
+    #[error(
+    "The literal `{literal}` does not fit into a register\n\
+    Note: Decimal literals have to be in the range {min}..={max}\n\
+    Note: Hex, binary and octal literals may go up to {bits:#x}", min = IRS::MIN, max = IRS::MAX, bits = URS::MAX
+    )]
+    LiteralOverflow { literal: String },
+    #[error("The expression `{expr}` is malformed at position {at}")]
+    InvalidExpression { expr: String, at: usize },
+    #[error("`{name}` is not a valid constant name")]
+    InvalidConstantName { name: String },
+    #[error("Expected a jump point, but found `{token}`")]
+    ExpectedJumpPoint { token: String },
+    #[error("`{name}` is not a valid macro name")]
+    InvalidMacroName { name: String },
+    #[error("`{name}` is not a valid macro parameter")]
+    InvalidMacroParameter { name: String },
+    #[error("The string literal `{literal}` is malformed")]
+    InvalidString { literal: String },
+    #[error("The argument `{token}` of the directive is invalid")]
+    InvalidDirectiveArgument { token: String },
+    #[error("The directive `{directive}` was used with the wrong arguments")]
+    InvalidDirectiveArguments { directive: String, usage: String },
     #[error("The line `{line}` contains more then two tokens")]
     TooManyTokens { line: String },
     #[error("The token `{token}` may not be the first in a line")]
@@ -67,4 +474,64 @@ pub enum ParseError {
     TokenDoesTakeAnArgument { token: CodeToken },
     #[error("The token `{token}` does not take an argument")]
     TokenDoesNotTakeAnArgument { token: CodeToken },
+    #[error(
+    "The instruction `{token}` does not access a data register, so its argument cannot be addressed\n\
+    Note: Only instructions like `LOAD`, `STORE` and `ADD` take `[n]` and `n[m]`"
+    )]
+    AddressingNotSupported { token: CodeToken },
+    #[error(
+    "The index register `{token}` is invalid\n\
+    Note: Index registers have to be a number in the range 0..{len}", len = DATA_REGISTERS
+    )]
+    InvalidIndexRegister { token: String },
+}
+
+impl ParseError {
+    /// A hint on how to fix the error.
+    pub fn help(&self) -> Option<String> {
+        match self {
+            Self::UnknownToken { .. } => Some(
+                "Valid tokens are instructions (`load`), instruction codes and numbers (`0`), \
+                jump points (`.name`) and jump point declarations (`.name:`)".to_owned()
+            ),
+            Self::InvalidLiteral { .. } => Some(
+                "Numbers can be written as `72`, `0x48`, `0b1001000`, `0o110` or `'H'`".to_owned()
+            ),
+            Self::LiteralOverflow { .. } => None,
+            Self::InvalidExpression { .. } => Some(
+                "Expressions consist of numbers, constants and jump points combined with \
+                `+`, `-`, `*`, `/`, `%` and parentheses".to_owned()
+            ),
+            Self::InvalidConstantName { .. } => Some(
+                "Constant names consist of letters, digits and `_`, and may not be instruction names".to_owned()
+            ),
+            Self::ExpectedJumpPoint { .. } => Some("Jump points are written like `.name`".to_owned()),
+            Self::InvalidMacroName { .. } => Some(
+                "Macro names consist of letters, digits and `_`, and may not be instruction names".to_owned()
+            ),
+            Self::InvalidMacroParameter { .. } => Some(
+                "Parameters consist of letters, digits and `_`, may not be instruction names and must be unique".to_owned()
+            ),
+            Self::InvalidString { .. } => Some(
+                "Strings are written in double quotes, like `\"Hello World!\\n\"`".to_owned()
+            ),
+            Self::InvalidDirectiveArgument { .. } => Some("The argument has to be a positive number".to_owned()),
+            Self::InvalidDirectiveArguments { usage, .. } => Some(format!("Use `{}`", usage)),
+            Self::TooManyTokens { .. } => Some(
+                "A line consists of an instruction and at most one argument, use `;` to start a comment".to_owned()
+            ),
+            Self::TokenMayNotBeFirst { .. } => Some(
+                "A line has to start with an instruction or a jump point declaration".to_owned()
+            ),
+            Self::TokenMayNotBeSecond { .. } => Some(
+                "Arguments have to be numbers or jump points".to_owned()
+            ),
+            Self::TokenDoesTakeAnArgument { token } => Some(format!("Add an argument after `{}`", token)),
+            Self::TokenDoesNotTakeAnArgument { .. } => Some("Remove the argument".to_owned()),
+            Self::AddressingNotSupported { .. } => Some("Write the argument without `[` and `]`".to_owned()),
+            Self::InvalidIndexRegister { .. } => Some(
+                "Write indexed arguments like `4[2]`, which accesses Rx[4 + Rx[2]]".to_owned()
+            ),
+        }
+    }
 }
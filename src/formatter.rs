@@ -0,0 +1,176 @@
+use crate::{Error, Result};
+use crate::lexer::code_line;
+use crate::lexer::code_token::CodeToken;
+use crate::lexer::directive::Directive;
+use crate::lexer::macros::Macro;
+use crate::lexer::spanned::Spanned;
+use crate::source::Location;
+
+/// Formats source code the same way, no matter how it was written.
+///
+/// Code is indented under the jump point it follows and inside of macros, instructions
+/// and directives are written in lowercase, and trailing comments are aligned into a column.
+/// Only whitespace and case change, so the formatted code assembles to the same RAM.
+#[derive(Clone, Debug)]
+pub struct Formatter {
+    /// The number of spaces a level of indentation is wide.
+    pub indent: usize,
+    /// The column trailing comments are aligned to, or `None` to place them
+    /// after the longest line with a trailing comment.
+    pub comment_column: Option<usize>,
+    /// Lines longer than this don't push the comments of all other lines further right.
+    pub max_comment_column: usize,
+}
+
+impl Default for Formatter {
+    fn default() -> Self {
+        Self {
+            indent: 4,
+            comment_column: None,
+            max_comment_column: 40,
+        }
+    }
+}
+
+/// A line split into its parts, before comments are aligned.
+struct Line<'a> {
+    indent: usize,
+    code: String,
+    comment: Option<&'a str>,
+}
+
+impl Line<'_> {
+    fn width(&self) -> usize {
+        self.indent + self.code.chars().count()
+    }
+}
+
+impl Formatter {
+    pub fn format(&self, source: &str) -> String {
+        let lines = self.split_lines(source);
+
+        let column = self.comment_column.unwrap_or_else(|| {
+            let longest = lines
+                .iter()
+                .filter(|line| !line.code.is_empty() && line.comment.is_some())
+                .map(|line| line.width() + 1)
+                .filter(|&width| width <= self.max_comment_column)
+                .max()
+                .unwrap_or(0);
+
+            match self.indent {
+                0 => longest,
+                indent => longest.div_ceil(indent) * indent,
+            }
+        });
+
+        let mut out = String::new();
+        for line in lines.iter() {
+            let mut formatted = format!("{:indent$}{}", "", line.code, indent = line.indent);
+            if let Some(comment) = line.comment {
+                if !line.code.is_empty() {
+                    let padding = column.saturating_sub(line.width()).max(1);
+                    formatted.push_str(&" ".repeat(padding));
+                }
+                formatted.push(';');
+                formatted.push_str(comment);
+            }
+
+            out.push_str(formatted.trim_end());
+            out.push('\n');
+        }
+
+        let len = out.trim_end_matches('\n').len();
+        out.truncate(len);
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Returns an error with the first line that is not formatted.
+    ///
+    /// The line is in the main file, use [`Location::file`] to name the file.
+    pub fn check(&self, source: &str) -> Result<()> {
+        let formatted = self.format(source);
+        if formatted == source {
+            return Ok(());
+        }
+
+        let line = source
+            .split('\n')
+            .zip(formatted.split('\n'))
+            .position(|(line, formatted)| line != formatted)
+            .unwrap_or_else(|| formatted.split('\n').count().min(source.split('\n').count()) - 1);
+        Err(Error::NotFormatted { line: Location::line(line + 1) })
+    }
+
+    fn split_lines<'a>(&self, source: &'a str) -> Vec<Line<'a>> {
+        let mut lines = Vec::new();
+        let mut in_macro = false;
+        let mut after_jump_point = false;
+        let mut after_jump_point_outside_macro = false;
+
+        for line in source.lines() {
+            let (code, comment) = code_line::split_comment(line);
+            let tokens = code_line::split_tokens(code);
+            let comment = comment.map(str::trim_end);
+
+            let base = if in_macro { self.indent } else { 0 };
+            let code_indent = base + if after_jump_point { self.indent } else { 0 };
+
+            let indent = if tokens.is_empty() {
+                code_indent
+            } else if Macro::is_start(&tokens) {
+                in_macro = true;
+                after_jump_point_outside_macro = after_jump_point;
+                after_jump_point = false;
+                0
+            } else if Macro::is_end(&tokens) {
+                in_macro = false;
+                after_jump_point = after_jump_point_outside_macro;
+                0
+            } else if is_jump_point_declaration(&tokens) {
+                after_jump_point = true;
+                base
+            } else if Directive::is_directive(&tokens) && !tokens[0].value.eq_ignore_ascii_case(".string") {
+                base
+            } else {
+                code_indent
+            };
+
+            lines.push(Line { indent, code: join_tokens(&tokens), comment });
+        }
+
+        lines
+    }
+}
+
+fn is_jump_point_declaration(tokens: &[Spanned<&str>]) -> bool {
+    match tokens {
+        [token] => matches!(CodeToken::from_str(token.value), Ok(CodeToken::JumpPointDeclaration(_))),
+        _ => false
+    }
+}
+
+/// Joins the tokens of a line with single spaces, with instructions and directives in lowercase.
+fn join_tokens(tokens: &[Spanned<&str>]) -> String {
+    tokens
+        .iter()
+        .enumerate()
+        .map(|(i, token)| {
+            let is_keyword = i == 0 && (
+                Directive::is_directive(tokens)
+                    || Macro::is_start(tokens)
+                    || Macro::is_end(tokens)
+                    || matches!(CodeToken::from_str(token.value), Ok(CodeToken::Inst(_)))
+            );
+
+            match is_keyword {
+                true => token.value.to_lowercase(),
+                false => token.value.to_owned(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
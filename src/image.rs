@@ -0,0 +1,427 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+use crate::{Error, IRS, RAM, Result, URS};
+use crate::disassembler::Disassembler;
+use crate::error::ImageError;
+use crate::instruction::Instruction;
+use crate::source::Location;
+use crate::source_map::SourceMap;
+
+/// An assembled program, that can be saved and loaded without the source code.
+///
+/// All numbers are stored in little endian:
+///
+/// ```text
+/// magic        b"KIMG"
+/// version      u16
+/// flags        u16         1 = symbols, 2 = source map
+/// entry point  u64
+/// code         u64 length, then (u64 instruction, i64 argument) for every word
+/// symbols      u32 length, then (string name, u64 address) for every symbol
+/// source map   u32 length, then (string file) for every included file,
+///              u64 length, then (u32 file, u64 line) for every word, file 0 is the main file
+/// ```
+///
+/// Strings are stored as their u32 length in bytes followed by UTF-8.
+#[derive(Clone, Debug, Default)]
+pub struct Image {
+    /// The value of BZ when the program starts.
+    pub entry: URS,
+    pub ram: RAM,
+    /// All jump points together with their address.
+    pub symbols: Option<BTreeMap<String, URS>>,
+    pub source_map: Option<SourceMap>,
+}
+
+const MAGIC: &[u8; 4] = b"KIMG";
+const VERSION: u16 = 1;
+
+const FLAG_SYMBOLS: u16 = 1;
+const FLAG_SOURCE_MAP: u16 = 2;
+
+impl Image {
+    pub fn new(ram: RAM) -> Self {
+        Self { ram, ..Self::default() }
+    }
+
+    /// Removes the optional symbol and source map sections.
+    pub fn strip(&mut self) {
+        self.symbols = None;
+        self.source_map = None;
+    }
+
+    /// The program as source code, with jump points named after the symbols.
+    pub fn disassemble(&self) -> Result<String> {
+        Disassembler::new(&self.ram)
+            .with_symbols(self.symbols.iter().flatten().map(|(name, &addr)| (name.as_str(), addr)))
+            .with_entry(self.entry)
+            .disassemble()
+    }
+
+    pub fn save<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load<R: Read>(r: &mut R) -> Result<Self> {
+        let mut bytes = Vec::new();
+        r.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if self.symbols.is_some() {
+            flags |= FLAG_SYMBOLS;
+        }
+        if self.source_map.is_some() {
+            flags |= FLAG_SOURCE_MAP;
+        }
+
+        let mut out = Vec::with_capacity(24 + self.ram.len() * 16);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&flags.to_le_bytes());
+        out.extend_from_slice(&self.entry.to_le_bytes());
+
+        out.extend_from_slice(&(self.ram.len() as u64).to_le_bytes());
+        for (inst, val) in self.ram.iter() {
+            out.extend_from_slice(&inst.to_le_bytes());
+            out.extend_from_slice(&val.to_le_bytes());
+        }
+
+        if let Some(ref symbols) = self.symbols {
+            out.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+            for (name, addr) in symbols.iter() {
+                write_string(&mut out, name);
+                out.extend_from_slice(&addr.to_le_bytes());
+            }
+        }
+
+        if let Some(ref source_map) = self.source_map {
+            let mut files = Vec::<&str>::new();
+            for file in source_map.lines().iter().filter_map(|location| location.file.as_deref()) {
+                if !files.contains(&file) {
+                    files.push(file);
+                }
+            }
+
+            out.extend_from_slice(&(files.len() as u32).to_le_bytes());
+            for file in files.iter() {
+                write_string(&mut out, file);
+            }
+
+            out.extend_from_slice(&(source_map.lines().len() as u64).to_le_bytes());
+            for location in source_map.lines() {
+                let file = location.file
+                    .as_deref()
+                    .and_then(|file| files.iter().position(|&f| f == file))
+                    .map(|i| i as u32 + 1)
+                    .unwrap_or(0);
+                out.extend_from_slice(&file.to_le_bytes());
+                out.extend_from_slice(&(location.line as u64).to_le_bytes());
+            }
+        }
+
+        out
+    }
+
+    /// Reads and validates an image.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::parse(&mut Reader { bytes, pos: 0 }).map_err(Error::InvalidImage)
+    }
+
+    fn parse(r: &mut Reader) -> Result<Self, ImageError> {
+        if r.take(4, "header").ok() != Some(&MAGIC[..]) {
+            return Err(ImageError::InvalidMagic);
+        }
+        let version = r.u16("header")?;
+        if version != VERSION {
+            return Err(ImageError::UnsupportedVersion { version });
+        }
+        let flags = r.u16("header")?;
+        if flags & !(FLAG_SYMBOLS | FLAG_SOURCE_MAP) != 0 {
+            return Err(ImageError::UnknownFlags { flags });
+        }
+        let entry = r.u64("header")?;
+
+        let len = r.u64("code")?;
+        let mut ram = RAM::new();
+        for address in 0..len {
+            let inst = r.u64("code")?;
+            let val = r.u64("code")? as IRS;
+            if Instruction::decode(inst).is_none() {
+                return Err(ImageError::UnknownOpcode { address, opcode: inst });
+            }
+            ram.push((inst, val));
+        }
+        if entry > 0 && entry >= len {
+            return Err(ImageError::InvalidEntryPoint { entry, len });
+        }
+
+        let symbols = if flags & FLAG_SYMBOLS != 0 {
+            let mut symbols = BTreeMap::new();
+            for _ in 0..r.u32("symbols")? {
+                let name = r.string("symbols")?;
+                let address = r.u64("symbols")?;
+                if address >= len {
+                    return Err(ImageError::InvalidAddress { name, address });
+                }
+                symbols.insert(name, address);
+            }
+            Some(symbols)
+        } else {
+            None
+        };
+
+        let source_map = if flags & FLAG_SOURCE_MAP != 0 {
+            let mut files = Vec::new();
+            for _ in 0..r.u32("source map")? {
+                files.push(Arc::<str>::from(r.string("source map")?));
+            }
+
+            let mut lines = Vec::new();
+            for _ in 0..r.u64("source map")? {
+                let file = match r.u32("source map")? {
+                    0 => None,
+                    index => Some(files
+                        .get(index as usize - 1)
+                        .cloned()
+                        .ok_or(ImageError::InvalidFileIndex { index })?),
+                };
+                lines.push(Location::new(file, r.u64("source map")? as usize));
+            }
+
+            let mut labels = BTreeMap::<URS, Vec<String>>::new();
+            for (name, &addr) in symbols.iter().flatten() {
+                labels.entry(addr).or_default().push(name.clone());
+            }
+            Some(SourceMap::new(lines, labels))
+        } else {
+            None
+        };
+
+        if r.pos < r.bytes.len() {
+            return Err(ImageError::TrailingBytes { len: r.bytes.len() - r.pos });
+        }
+
+        Ok(Self { entry, ram, symbols, source_map })
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize, section: &'static str) -> Result<&'a [u8], ImageError> {
+        let bytes = self.bytes
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or(ImageError::Truncated { section })?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self, section: &'static str) -> Result<u16, ImageError> {
+        let mut buf = [0; 2];
+        buf.copy_from_slice(self.take(2, section)?);
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn u32(&mut self, section: &'static str) -> Result<u32, ImageError> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4, section)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self, section: &'static str) -> Result<u64, ImageError> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8, section)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn string(&mut self, section: &'static str) -> Result<String, ImageError> {
+        let len = self.u32(section)? as usize;
+        String::from_utf8(self.take(len, section)?.to_vec())
+            .map_err(|_| ImageError::InvalidString { section })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::Document;
+    use crate::lexer::options::Options;
+    use crate::source::InMemory;
+
+    use super::*;
+
+    const MAIN: &str = "\
+.entry .main
+.print:
+    int 2
+    ret
+.main:
+    dload 1
+    call .print
+    .include \"lib.kasm\"
+    end
+";
+
+    const LIB: &str = "\
+dload 2
+call .print
+";
+
+    fn image() -> Image {
+        let mut provider = InMemory::default();
+        provider.insert("lib.kasm", LIB);
+        let (doc, diagnostics) = Document::assemble_with(MAIN, Some("main.kasm"), &provider, &Options::default());
+        doc.unwrap_or_else(|| panic!("{}", diagnostics.render(MAIN))).image()
+    }
+
+    fn assert_same(image: &Image, loaded: &Image) {
+        assert_eq!(loaded.entry, image.entry);
+        assert_eq!(loaded.ram, image.ram);
+        assert_eq!(loaded.symbols, image.symbols);
+        assert_eq!(
+            loaded.source_map.as_ref().map(SourceMap::lines),
+            image.source_map.as_ref().map(SourceMap::lines),
+        );
+    }
+
+    fn reject(bytes: &[u8]) -> ImageError {
+        match Image::from_bytes(bytes) {
+            Err(Error::InvalidImage(err)) => err,
+            result => panic!("expected an invalid image, got {:?}", result),
+        }
+    }
+
+    /// An image with the given header and code, but no symbols or source map.
+    fn bytes(entry: URS, ram: &[(URS, IRS)]) -> Vec<u8> {
+        Image { entry, ..Image::new(ram.to_vec()) }.to_bytes()
+    }
+
+    #[test]
+    fn round_trip() {
+        let image = image();
+        assert_eq!(image.entry, 3);
+        assert!(image.source_map.as_ref().unwrap().lines().iter().any(|location| !location.is_main()));
+
+        let mut saved = Vec::new();
+        image.save(&mut saved).unwrap();
+        let loaded = Image::load(&mut saved.as_slice()).unwrap();
+        assert_same(&image, &loaded);
+    }
+
+    #[test]
+    fn round_trip_stripped() {
+        let mut image = image();
+        image.strip();
+
+        let loaded = Image::from_bytes(&image.to_bytes()).unwrap();
+        assert!(loaded.symbols.is_none());
+        assert!(loaded.source_map.is_none());
+        assert_same(&image, &loaded);
+    }
+
+    #[test]
+    fn round_trip_empty() {
+        let loaded = Image::from_bytes(&Image::default().to_bytes()).unwrap();
+        assert_same(&Image::default(), &loaded);
+    }
+
+    #[test]
+    fn invalid_magic() {
+        let mut bytes = image().to_bytes();
+        bytes[0] = b'X';
+        assert!(matches!(reject(&bytes), ImageError::InvalidMagic));
+        assert!(matches!(reject(b"KIM"), ImageError::InvalidMagic));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut bytes = image().to_bytes();
+        bytes[4..6].copy_from_slice(&2u16.to_le_bytes());
+        assert!(matches!(reject(&bytes), ImageError::UnsupportedVersion { version: 2 }));
+    }
+
+    #[test]
+    fn unknown_flags() {
+        let mut bytes = image().to_bytes();
+        bytes[6..8].copy_from_slice(&7u16.to_le_bytes());
+        assert!(matches!(reject(&bytes), ImageError::UnknownFlags { flags: 7 }));
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = image().to_bytes();
+        assert!(matches!(reject(&bytes[..10]), ImageError::Truncated { section: "header" }));
+        assert!(matches!(reject(&bytes[..30]), ImageError::Truncated { section: "code" }));
+        assert!(matches!(reject(&bytes[..bytes.len() - 1]), ImageError::Truncated { section: "source map" }));
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let bytes = bytes(0, &[(Instruction::NOOP as URS, 0), (1000, 0)]);
+        assert!(matches!(reject(&bytes), ImageError::UnknownOpcode { address: 1, opcode: 1000 }));
+    }
+
+    #[test]
+    fn invalid_entry_point() {
+        let bytes = bytes(2, &[(Instruction::NOOP as URS, 0), (Instruction::END as URS, 0)]);
+        assert!(matches!(reject(&bytes), ImageError::InvalidEntryPoint { entry: 2, len: 2 }));
+    }
+
+    #[test]
+    fn invalid_address() {
+        let image = Image {
+            symbols: Some(vec![("outside".to_owned(), 1)].into_iter().collect()),
+            ..Image::new(vec![(Instruction::END as URS, 0)])
+        };
+        assert!(matches!(
+            reject(&image.to_bytes()),
+            ImageError::InvalidAddress { ref name, address: 1 } if name == "outside"
+        ));
+    }
+
+    #[test]
+    fn invalid_file_index() {
+        let image = Image {
+            source_map: Some(SourceMap::new(vec![Location::line(1)], BTreeMap::new())),
+            ..Image::new(vec![(Instruction::END as URS, 0)])
+        };
+        let mut bytes = image.to_bytes();
+        // no files, then one line whose file index follows
+        let index = bytes.len() - 12;
+        bytes[index..index + 4].copy_from_slice(&1u32.to_le_bytes());
+        assert!(matches!(reject(&bytes), ImageError::InvalidFileIndex { index: 1 }));
+    }
+
+    #[test]
+    fn invalid_string() {
+        let image = Image {
+            symbols: Some(vec![("ab".to_owned(), 0)].into_iter().collect()),
+            ..Image::new(vec![(Instruction::END as URS, 0)])
+        };
+        let mut bytes = image.to_bytes();
+        // the name is followed by its u64 address
+        let name = bytes.len() - 10;
+        bytes[name] = 0xff;
+        assert!(matches!(reject(&bytes), ImageError::InvalidString { section: "symbols" }));
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let mut bytes = image().to_bytes();
+        bytes.extend_from_slice(&[0, 0, 0]);
+        assert!(matches!(reject(&bytes), ImageError::TrailingBytes { len: 3 }));
+    }
+}
@@ -1,6 +1,14 @@
 use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
 use strum::EnumString;
 
+use crate::{DATA_REGISTERS, URS};
+
+/// The bit at which the addressing mode starts in an instruction word.
+const ADDRESSING_SHIFT: u32 = 56;
+/// The bit at which the index register of indexed addressing starts in an instruction word.
+const INDEX_SHIFT: u32 = 48;
+
 #[repr(u64)]
 #[derive(Clone, Copy, Debug, EnumString, FromPrimitive, derive_more::Display, strum::EnumVariantNames)]
 pub enum Instruction {
@@ -23,24 +31,194 @@ pub enum Instruction {
     BP,
     NOOP,
     INT,
+    MLOAD,
+    MSTORE,
+    CALL,
+    RET,
+    PUSH,
+    POP,
+    MOD,
+    AND,
+    OR,
+    XOR,
+    NOT,
+    SHL,
+    SHR,
+    SAR,
+    NEG,
+    DADD,
+    DSUB,
+    DMULT,
+    DDIV,
+    DMOD,
+    DAND,
+    DOR,
+    DXOR,
+    DSHL,
+    DSHR,
+    DSAR,
+    JC,
+    JNC,
+    JO,
+    JNO,
+    JZ,
+    JNZ,
+    JN,
+    JNN,
 }
 
 impl Instruction {
     pub fn takes_argument(self) -> bool {
         use Instruction::*;
 
-        match self {
-            END | BP | NOOP => false,
-            _ => true
-        }
+        !matches!(self, END | BP | NOOP | RET | NOT | NEG)
     }
 }
 
 impl Instruction {
     pub fn takes_value(self) -> bool {
+        !matches!(self, Self::END | Self::BP | Self::NOOP | Self::RET | Self::NOT | Self::NEG)
+    }
+
+    /// What the instruction does, `n` stands for the argument.
+    pub fn description(self) -> &'static str {
+        use Instruction::*;
+
+        match self {
+            LOAD => "A = Rx[n]",
+            DLOAD => "A = n",
+            STORE => "Rx[n] = A",
+            ADD => "A = A + Rx[n]",
+            SUB => "A = A - Rx[n]",
+            MULT => "A = A * Rx[n]",
+            DIV => "A = A / Rx[n], fails if Rx[n] is 0",
+            JUMP => "BZ = n",
+            JGE => "BZ = n if A >= 0",
+            JGT => "BZ = n if A > 0",
+            JLE => "BZ = n if A <= 0",
+            JLT => "BZ = n if A < 0",
+            JEQ => "BZ = n if A == 0",
+            JNE => "BZ = n if A != 0",
+            END => "Ends the program",
+            BP => "Stops at a break point",
+            NOOP => "Does nothing",
+            INT => "Executes the interrupt n",
+            MLOAD => "A = M[Rx[n]], where M is the data memory",
+            MSTORE => "M[Rx[n]] = A, where M is the data memory",
+            CALL => "Pushes the address of the next instruction onto the stack, and sets BZ = n",
+            RET => "Pops the return address of the last CALL from the stack into BZ",
+            PUSH => "Pushes Rx[n] onto the stack",
+            POP => "Pops the top of the stack into Rx[n]",
+            MOD => "A = A % Rx[n], the result has the sign of A, fails if Rx[n] is 0",
+            AND => "A = A & Rx[n]",
+            OR => "A = A | Rx[n]",
+            XOR => "A = A ^ Rx[n]",
+            NOT => "A = !A, inverts all bits",
+            SHL => "A = A << Rx[n], A = 0 if Rx[n] is not in 0..64",
+            SHR => "A = A >> Rx[n] shifting in zeros, A = 0 if Rx[n] is not in 0..64",
+            SAR => "A = A >> Rx[n] shifting in the sign bit, A = 0 or -1 if Rx[n] is not in 0..64",
+            NEG => "A = -A",
+            DADD => "A = A + n",
+            DSUB => "A = A - n",
+            DMULT => "A = A * n",
+            DDIV => "A = A / n, fails if n is 0",
+            DMOD => "A = A % n, the result has the sign of A, fails if n is 0",
+            DAND => "A = A & n",
+            DOR => "A = A | n",
+            DXOR => "A = A ^ n",
+            DSHL => "A = A << n, A = 0 if n is not in 0..64",
+            DSHR => "A = A >> n shifting in zeros, A = 0 if n is not in 0..64",
+            DSAR => "A = A >> n shifting in the sign bit, A = 0 or -1 if n is not in 0..64",
+            JC => "BZ = n if the carry flag is set",
+            JNC => "BZ = n if the carry flag is not set",
+            JO => "BZ = n if the overflow flag is set",
+            JNO => "BZ = n if the overflow flag is not set",
+            JZ => "BZ = n if the zero flag is set",
+            JNZ => "BZ = n if the zero flag is not set",
+            JN => "BZ = n if the negative flag is set",
+            JNN => "BZ = n if the negative flag is not set",
+        }
+    }
+
+    /// Whether the argument selects a data register, so the instruction supports all addressing modes.
+    pub fn takes_register(self) -> bool {
+        use Instruction::*;
+
+        matches!(
+            self,
+            LOAD | STORE | ADD | SUB | MULT | DIV | MLOAD | MSTORE | PUSH | POP | MOD | AND | OR | XOR | SHL | SHR | SAR
+        )
+    }
+
+    /// The instruction word of the instruction with an addressing mode.
+    pub fn encode(self, addressing: Addressing) -> URS {
+        addressing.encode(self as URS)
+    }
+
+    /// Splits an instruction word into the instruction and its addressing mode.
+    ///
+    /// Fails for unknown instructions, and for addressing modes the instruction does not support.
+    pub fn decode(word: URS) -> Option<(Self, Addressing)> {
+        let (code, addressing) = Addressing::decode(word)?;
+        let inst = Self::from_u64(code)?;
+
+        match addressing {
+            Addressing::Direct => Some((inst, addressing)),
+            _ if inst.takes_register() => Some((inst, addressing)),
+            _ => None
+        }
+    }
+
+    /// Whether the argument is an address in RAM.
+    pub fn is_jump(self) -> bool {
+        use Instruction::*;
+
+        matches!(self, JUMP | JGE | JGT | JLE | JLT | JEQ | JNE | CALL | JC | JNC | JO | JNO | JZ | JNZ | JN | JNN)
+    }
+}
+
+/// How an instruction finds the data register it accesses.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Addressing {
+    /// `LOAD n` accesses `Rx[n]`.
+    Direct,
+    /// `LOAD [n]` accesses `Rx[Rx[n]]`.
+    Indirect,
+    /// `LOAD n[m]` accesses `Rx[n + Rx[m]]`.
+    Indexed(u8),
+}
+
+impl Addressing {
+    /// Adds the addressing mode to an instruction code in the upper bits of the word.
+    ///
+    /// Direct addressing leaves the code unchanged.
+    pub fn encode(self, code: URS) -> URS {
+        match self {
+            Self::Direct => code,
+            Self::Indirect => code | 1 << ADDRESSING_SHIFT,
+            Self::Indexed(m) => code | 2 << ADDRESSING_SHIFT | (m as URS) << INDEX_SHIFT,
+        }
+    }
+
+    /// Splits an instruction word into the instruction code and the addressing mode.
+    pub fn decode(word: URS) -> Option<(URS, Self)> {
+        let code = word & ((1 << INDEX_SHIFT) - 1);
+        let index = (word >> INDEX_SHIFT) as u8;
+
+        match word >> ADDRESSING_SHIFT {
+            0 => Some((word, Self::Direct)),
+            1 if index == 0 => Some((code, Self::Indirect)),
+            2 if (index as usize) < DATA_REGISTERS => Some((code, Self::Indexed(index))),
+            _ => None
+        }
+    }
+
+    /// Writes an argument the way it is written in source code, like `[n]` or `n[m]`.
+    pub fn format<T: std::fmt::Display>(self, argument: T) -> String {
         match self {
-            Self::END | Self::BP | Self::NOOP => false,
-            _ => true
+            Self::Direct => argument.to_string(),
+            Self::Indirect => format!("[{}]", argument),
+            Self::Indexed(m) => format!("{}[{}]", argument, m),
         }
     }
 }
@@ -1,7 +1,7 @@
 use num_derive::FromPrimitive;
 
 #[repr(u64)]
-#[derive(Clone, Copy, Debug, FromPrimitive, strum::EnumVariantNames)]
+#[derive(Clone, Copy, Debug, FromPrimitive, derive_more::Display, strum::EnumVariantNames)]
 pub enum Interrupt {
     Print,
     PrintBytes,
@@ -10,4 +10,24 @@ pub enum Interrupt {
     DumpBZ,
     DumpRx,
     DumpRam,
+    DumpMem,
+    DumpStack,
+}
+
+impl Interrupt {
+    /// What the interrupt does.
+    pub fn description(self) -> &'static str {
+        use Interrupt::*;
+
+        match self {
+            Print => "Prints the data registers as UTF-8, up to the last register that is not 0",
+            PrintBytes => "Prints the bytes of the data registers, up to the last register that is not 0",
+            DumpA => "Prints A",
+            DumpBZ => "Prints BZ",
+            DumpRx => "Prints all data registers",
+            DumpRam => "Prints the RAM",
+            DumpMem => "Prints the data memory, up to the last word that is not 0",
+            DumpStack => "Prints the stack, with the top of the stack last",
+        }
+    }
 }
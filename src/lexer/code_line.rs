@@ -1,78 +1,301 @@
-use crate::{IRS, URS};
+use std::ops::Range;
+
+use num_traits::FromPrimitive;
+
+use crate::{DATA_REGISTERS, IRS, URS};
 use crate::error::ParseError;
+use crate::instruction::{Addressing, Instruction};
 
 use super::code_token::CodeToken;
+use super::expression::Expression;
+use super::literal::Literal;
+use super::spanned::Spanned;
 
 #[derive(Debug)]
 pub enum CodeLine {
-    SingleToken(CodeToken),
-    DoubleToken(CodeToken, CodeToken),
+    SingleToken(Spanned<CodeToken>),
+    /// An instruction with an argument, and how the argument selects a data register.
+    DoubleToken(Spanned<CodeToken>, Spanned<CodeToken>, Addressing),
 }
 
 impl CodeLine {
-    pub fn from_str(s: &str) -> Result<Option<Self>, ParseError> {
-        let code = trim_comments(s);
-        let mut code_parts = code.split_ascii_whitespace();
+    pub fn from_str(s: &str) -> Result<Option<Self>, Spanned<ParseError>> {
+        Self::from_tokens(s, &split_tokens(s))
+    }
 
-        let ct0 = match code_parts.next() {
-            Some(token) => CodeToken::from_str(token)?,
+    /// Parses a line that was already split into tokens by [`split_tokens`].
+    pub fn from_tokens(s: &str, tokens: &[Spanned<&str>]) -> Result<Option<Self>, Spanned<ParseError>> {
+        let ct0 = match tokens.first() {
+            Some(token) => parse_token(token)?,
             None => return Ok(None)
         };
 
-        let cl = match code_parts.next() {
-            Some(token) => {
-                let ct1 = CodeToken::from_str(token)?;
-                Self::DoubleToken(ct0, ct1)
-            },
-            None => Self::SingleToken(ct0)
+        let argument = match tokens {
+            [_] => return Ok(Some(Self::SingleToken(ct0))),
+            [_, argument] => argument.clone(),
+            [_, first, .., last] => {
+                let columns = first.columns.start..last.columns.end;
+                Spanned::new(slice_columns(s, columns.clone()), columns)
+            }
+            [] => unreachable!(),
         };
+        let (argument, addressing) = split_addressing(argument)?;
 
-        match code_parts.next() {
-            Some(_) => Err(ParseError::TooManyTokens { line: s.to_owned() }),
-            None => Ok(Some(cl))
+        if let [_, _, third, .., last] = tokens {
+            // arguments may be expressions with whitespaces, like `NUMBER + 1`
+            return match Expression::parse(argument.value) {
+                Ok(expr) => Ok(Some(Self::DoubleToken(ct0, argument.map(|_| CodeToken::Expr(expr)), addressing))),
+                Err(_) => Err(Spanned::new(
+                    ParseError::TooManyTokens { line: s.to_owned() },
+                    third.columns.start..last.columns.end,
+                )),
+            };
         }
+
+        let ct1 = match CodeToken::from_str_argument(argument.value) {
+            Ok(ct) => Spanned::new(ct, argument.columns),
+            Err(err) => return Err(Spanned::new(err, argument.columns)),
+        };
+        Ok(Some(Self::DoubleToken(ct0, ct1, addressing)))
     }
 
     pub fn as_urs_irs(&self) -> (URS, IRS) {
         match self {
-            Self::SingleToken(ct) => (ct.as_urs(), 0),
-            Self::DoubleToken(ct0, ct1) => (ct0.as_urs(), ct1.as_irs())
+            Self::SingleToken(ct) => (ct.value.as_urs(), 0),
+            Self::DoubleToken(ct0, ct1, addressing) => (addressing.encode(ct0.value.as_urs()), ct1.value.as_irs())
         }
     }
 
-    pub fn check(&self) -> Result<(), ParseError> {
+    pub fn check(&self) -> Result<(), Spanned<ParseError>> {
         match self {
             CodeLine::SingleToken(ct) => Self::check_single(ct),
-            CodeLine::DoubleToken(ct0, ct1) => Self::check_double(ct0, ct1)
+            CodeLine::DoubleToken(ct0, ct1, addressing) => Self::check_double(ct0, ct1, *addressing)
+        }
+    }
+
+    /// The instruction of this line, if the first token is an instruction or an instruction code.
+    pub fn instruction(&self) -> Option<Instruction> {
+        let ct = match self {
+            Self::SingleToken(ct) | Self::DoubleToken(ct, ..) => &ct.value
+        };
+
+        match *ct {
+            CodeToken::Inst(inst) => Some(inst),
+            CodeToken::Code(code) => Instruction::from_u64(code),
+            _ => None
+        }
+    }
+
+    pub fn as_jump_point_declaration(&self) -> Option<&super::jump_point::JumpPoint> {
+        match self {
+            Self::SingleToken(ct) => ct.value.as_jump_point_declaration(),
+            Self::DoubleToken(..) => None
+        }
+    }
+
+    /// The columns of all tokens in this line.
+    pub fn columns(&self) -> Range<usize> {
+        match self {
+            Self::SingleToken(ct) => ct.columns.clone(),
+            Self::DoubleToken(ct0, ct1, addressing) => match addressing {
+                // the argument is followed by `]`
+                Addressing::Indirect | Addressing::Indexed(_) => ct0.columns.start..ct1.columns.end + 1,
+                Addressing::Direct => ct0.columns.start..ct1.columns.end,
+            }
+        }
+    }
+
+    /// Moves all tokens to the same columns.
+    ///
+    /// Used for lines that were generated from other source code, like macro expansions.
+    pub fn set_columns(&mut self, columns: Range<usize>) {
+        match self {
+            Self::SingleToken(ct) => ct.columns = columns,
+            Self::DoubleToken(ct0, ct1, _) => {
+                ct0.columns = columns.clone();
+                ct1.columns = columns;
+            }
         }
     }
 
-    fn check_single(ct: &CodeToken) -> Result<(), ParseError> {
-        if !ct.can_be_first() {
-            Err(ParseError::TokenMayNotBeFirst { token: ct.clone() })
-        } else if ct.takes_second() {
-            Err(ParseError::TokenDoesTakeAnArgument { token: ct.clone() })
+    /// Renames all jump points and jump point declarations for which `rename` returns a new name.
+    pub fn rename_jump_points<F: Fn(&str) -> Option<String>>(&mut self, rename: &F) {
+        let ct = match self {
+            Self::SingleToken(ct) | Self::DoubleToken(_, ct, _) => &mut ct.value
+        };
+
+        match ct {
+            CodeToken::JumpPoint(jp) | CodeToken::JumpPointDeclaration(jp) => {
+                if let Some(name) = rename(jp.as_ref()) {
+                    jp.0 = name;
+                }
+            }
+            CodeToken::Expr(expr) => expr.rename_jump_points(rename),
+            _ => {}
+        }
+    }
+
+    fn check_single(ct: &Spanned<CodeToken>) -> Result<(), Spanned<ParseError>> {
+        if !ct.value.can_be_first() {
+            Err(error_at(ct, |token| ParseError::TokenMayNotBeFirst { token }))
+        } else if ct.value.takes_second() {
+            Err(error_at(ct, |token| ParseError::TokenDoesTakeAnArgument { token }))
         } else {
             Ok(())
         }
     }
 
-    fn check_double(ct0: &CodeToken, ct1: &CodeToken) -> Result<(), ParseError> {
-        if !ct0.can_be_first() {
-            Err(ParseError::TokenMayNotBeFirst { token: ct0.clone() })
-        } else if !ct1.can_be_second() {
-            Err(ParseError::TokenMayNotBeSecond { token: ct1.clone() })
-        } else if !ct0.takes_second() {
-            Err(ParseError::TokenDoesNotTakeAnArgument { token: ct0.clone() })
+    fn check_double(ct0: &Spanned<CodeToken>, ct1: &Spanned<CodeToken>, addressing: Addressing) -> Result<(), Spanned<ParseError>> {
+        if !ct0.value.can_be_first() {
+            Err(error_at(ct0, |token| ParseError::TokenMayNotBeFirst { token }))
+        } else if !ct1.value.can_be_second() {
+            Err(error_at(ct1, |token| ParseError::TokenMayNotBeSecond { token }))
+        } else if !ct0.value.takes_second() {
+            Err(error_at(ct1, |_| ParseError::TokenDoesNotTakeAnArgument { token: ct0.value.clone() }))
+        } else if addressing != Addressing::Direct && !ct0.value.takes_register() {
+            Err(error_at(ct1, |_| ParseError::AddressingNotSupported { token: ct0.value.clone() }))
         } else {
             Ok(())
         }
     }
 }
 
+fn error_at<F: FnOnce(CodeToken) -> ParseError>(ct: &Spanned<CodeToken>, err: F) -> Spanned<ParseError> {
+    ct.clone().map(err)
+}
+
+fn parse_token(token: &Spanned<&str>) -> Result<Spanned<CodeToken>, Spanned<ParseError>> {
+    match CodeToken::from_str(token.value) {
+        Ok(ct) => Ok(Spanned::new(ct, token.columns.clone())),
+        Err(err) => Err(Spanned::new(err, token.columns.clone())),
+    }
+}
+
+/// Splits the addressing mode from an argument, like `[n]` or `n[m]`.
+fn split_addressing(argument: Spanned<&str>) -> Result<(Spanned<&str>, Addressing), Spanned<ParseError>> {
+    let s = argument.value;
+    if s.starts_with(['"', '\'']) || !s.ends_with(']') {
+        return Ok((argument, Addressing::Direct));
+    }
+
+    let start = argument.columns.start;
+    let len = s.chars().count();
+    if let Some(inner) = s.strip_prefix('[') {
+        let inner = inner[..inner.len() - 1].trim();
+        let offset = s[1..].chars().take_while(char::is_ascii_whitespace).count() + 1;
+        let columns = start + offset..start + offset + inner.chars().count();
+        return Ok((Spanned::new(inner, columns), Addressing::Indirect));
+    }
+
+    let open = match s.rfind('[') {
+        Some(open) => open,
+        None => return Ok((argument, Addressing::Direct)),
+    };
+    let base = s[..open].trim_end();
+    let index = s[open + 1..s.len() - 1].trim();
+    let index_columns = start + s[..open + 1].chars().count()..start + len - 1;
+
+    let m = match Literal::parse(index) {
+        Ok(Some(Literal::Unsigned(m))) => Some(m as IRS),
+        Ok(Some(Literal::Signed(m))) => Some(m),
+        _ => None,
+    };
+    match m {
+        Some(m) if (0..DATA_REGISTERS as IRS).contains(&m) => Ok((
+            Spanned::new(base, start..start + base.chars().count()),
+            Addressing::Indexed(m as u8),
+        )),
+        _ => Err(Spanned::new(ParseError::InvalidIndexRegister { token: index.to_owned() }, index_columns)),
+    }
+}
+
+/// Returns the part of `s` between the given character columns.
+pub fn slice_columns(s: &str, columns: Range<usize>) -> &str {
+    let mut indices = s
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(s.len()));
+
+    let start = indices.nth(columns.start).unwrap_or(s.len());
+    let end = match columns.end.checked_sub(columns.start + 1) {
+        Some(n) => indices.nth(n).unwrap_or(s.len()),
+        None => start,
+    };
+
+    &s[start..end]
+}
+
+/// Removes the comment from a line and splits the remaining code into tokens.
+pub fn split_tokens(s: &str) -> Vec<Spanned<&str>> {
+    tokenize(trim_comments(s))
+}
+
+/// Splits a line at whitespaces and returns each token together with its columns.
+///
+/// Whitespaces inside of quotes don't split tokens.
+fn tokenize(code: &str) -> Vec<Spanned<&str>> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut quotes = Quotes::default();
+
+    for (column, (i, c)) in code.char_indices().enumerate() {
+        let in_quotes = quotes.in_quotes();
+        quotes.push(c);
+
+        match (start, c.is_ascii_whitespace() && !in_quotes) {
+            (None, false) => start = Some((column, i)),
+            (Some((start_column, start_i)), true) => {
+                tokens.push(Spanned::new(&code[start_i..i], start_column..column));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    if let Some((start_column, start_i)) = start {
+        tokens.push(Spanned::new(&code[start_i..], start_column..code.chars().count()));
+    }
+
+    tokens
+}
+
 fn trim_comments(s: &str) -> &str {
-    match s.split_once(';') {
-        Some((code, _)) => code,
-        None => s
+    split_comment(s).0
+}
+
+/// Splits a line into its code and its comment without the leading `;`, if it has one.
+pub fn split_comment(s: &str) -> (&str, Option<&str>) {
+    let mut quotes = Quotes::default();
+
+    for (i, c) in s.char_indices() {
+        if c == ';' && !quotes.in_quotes() {
+            return (&s[..i], Some(&s[i + 1..]));
+        }
+        quotes.push(c);
+    }
+
+    (s, None)
+}
+
+/// Keeps track of whether a position in a line is inside of `'` or `"` quotes.
+#[derive(Default)]
+pub(super) struct Quotes {
+    quote: Option<char>,
+    escaped: bool,
+}
+
+impl Quotes {
+    pub(super) fn in_quotes(&self) -> bool {
+        self.quote.is_some()
+    }
+
+    pub(super) fn push(&mut self, c: char) {
+        match self.quote {
+            Some(_) if self.escaped => self.escaped = false,
+            Some(_) if c == '\\' => self.escaped = true,
+            Some(quote) if c == quote => self.quote = None,
+            None if c == '\'' || c == '"' => self.quote = Some(c),
+            _ => {}
+        }
     }
 }
@@ -5,7 +5,9 @@ use num_traits::FromPrimitive;
 use crate::{IRS, URS};
 use crate::error::ParseError;
 use crate::instruction::Instruction;
+use crate::lexer::expression::Expression;
 use crate::lexer::jump_point::JumpPoint;
+use crate::lexer::literal::Literal;
 
 #[derive(Clone, Debug, derive_more::Display)]
 pub enum CodeToken {
@@ -15,26 +17,27 @@ pub enum CodeToken {
     Val(IRS),
     #[display(fmt = "{}", _0)]
     Code(URS),
-    #[display(fmt = "{}", _0)]
+    #[display(fmt = ".{}", _0)]
     JumpPoint(JumpPoint),
-    #[display(fmt = "{}", _0)]
+    #[display(fmt = ".{}:", _0)]
     JumpPointDeclaration(JumpPoint),
+    #[display(fmt = "{}", _0)]
+    Expr(Expression),
 }
 
 impl CodeToken {
+    /// Parses a single token.
+    ///
+    /// Instructions are case-insensitive, while jump points keep their original spelling.
     pub fn from_str(s: &str) -> crate::Result<Self, ParseError> {
-        let ref s = s.to_uppercase();
-
-        if let Some(inst) = Self::parse(s) {
+        if let Some(inst) = Self::parse(&s.to_uppercase()) {
             return Ok(Self::Inst(inst));
         }
 
-        if let Some(code) = Self::parse(s) {
-            return Ok(Self::Code(code));
-        }
-
-        if let Some(val) = Self::parse(s) {
-            return Ok(Self::Val(val));
+        match Literal::parse(s)? {
+            Some(Literal::Unsigned(code)) => return Ok(Self::Code(code)),
+            Some(Literal::Signed(val)) => return Ok(Self::Val(val)),
+            None => {}
         }
 
         if let Some(jump_point) = Self::parse(s) {
@@ -52,6 +55,25 @@ impl CodeToken {
         Err(ParseError::UnknownToken { token: s.to_owned() })
     }
 
+    /// Parses the argument of an instruction.
+    ///
+    /// In addition to the tokens accepted by [`CodeToken::from_str`], arguments
+    /// may be constant expressions.
+    pub fn from_str_argument(s: &str) -> crate::Result<Self, ParseError> {
+        match Self::from_str(s) {
+            Err(ParseError::UnknownToken { token }) => match Expression::parse(s) {
+                Ok(expr) => Ok(Self::Expr(expr)),
+                Err(err) if s.contains(|c| "+-*/%()".contains(c)) => Err(err),
+                Err(_) => Err(ParseError::UnknownToken { token }),
+            },
+            // expressions like `'A'+2` start like a literal
+            Err(err @ ParseError::InvalidLiteral { .. }) => Expression::parse(s)
+                .map(Self::Expr)
+                .map_err(|_| err),
+            res => res
+        }
+    }
+
     pub fn as_urs(&self) -> URS {
         use CodeToken::*;
 
@@ -62,6 +84,7 @@ impl CodeToken {
             JumpPoint(_) | JumpPointDeclaration(_) => {
                 panic!("JumpPoints and JumpPointDeclarations must be resolved before converting to URS")
             }
+            Expr(_) => panic!("Expressions must be evaluated before converting to URS")
         }
     }
 
@@ -75,20 +98,21 @@ impl CodeToken {
             JumpPoint(_) | JumpPointDeclaration(_) => {
                 panic!("JumpPoints and JumpPointDeclarations must be resolved before converting to URS")
             }
+            Expr(_) => panic!("Expressions must be evaluated before converting to IRS")
         }
     }
 
     pub fn can_be_first(&self) -> bool {
         match self {
             Self::Inst(_) | Self::Code(_) | Self::JumpPointDeclaration(_) => true,
-            Self::Val(_) | Self::JumpPoint(_) => false
+            Self::Val(_) | Self::JumpPoint(_) | Self::Expr(_) => false
         }
     }
 
     pub fn can_be_second(&self) -> bool {
         match self {
             Self::Inst(_) | Self::JumpPointDeclaration(_) => false,
-            Self::Code(_) | Self::Val(_) | Self::JumpPoint(_) => true
+            Self::Code(_) | Self::Val(_) | Self::JumpPoint(_) | Self::Expr(_) => true
         }
     }
 
@@ -100,7 +124,20 @@ impl CodeToken {
                     .map(Instruction::takes_value)
                     .unwrap_or(true)
             }
-            Self::Val(_) | Self::JumpPoint(_) | Self::JumpPointDeclaration(_) => false
+            Self::Val(_) | Self::JumpPoint(_) | Self::JumpPointDeclaration(_) | Self::Expr(_) => false
+        }
+    }
+
+    /// Whether the token is an instruction that supports all addressing modes, like `LOAD [n]`.
+    pub fn takes_register(&self) -> bool {
+        match self {
+            Self::Inst(inst) => inst.takes_register(),
+            Self::Code(c) => {
+                Instruction::from_u64(*c)
+                    .map(Instruction::takes_register)
+                    .unwrap_or(true)
+            }
+            Self::Val(_) | Self::JumpPoint(_) | Self::JumpPointDeclaration(_) | Self::Expr(_) => false
         }
     }
 
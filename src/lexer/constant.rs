@@ -0,0 +1,22 @@
+use crate::IRS;
+use crate::diagnostic::Span;
+use crate::source::Location;
+
+use super::expression::Expression;
+use super::spanned::Spanned;
+
+/// A named constant defined by a `.equ` directive.
+#[derive(Clone, Debug)]
+pub struct Constant {
+    pub name: Spanned<String>,
+    pub line: Location,
+    pub expr: Spanned<Expression>,
+    /// The value of the constant, once it was evaluated.
+    pub value: Option<IRS>,
+}
+
+impl Constant {
+    pub fn span(&self) -> Span {
+        Span::new(self.line.clone(), self.name.columns.clone())
+    }
+}
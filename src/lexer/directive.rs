@@ -0,0 +1,218 @@
+use crate::{DATA_REGISTERS, Error, IRS, URS};
+use crate::error::ParseError;
+use crate::instruction::{Addressing, Instruction};
+use crate::source::Location;
+
+use super::code_line::{self, CodeLine};
+use super::code_token::CodeToken;
+use super::expression::Expression;
+use super::jump_point::JumpPoint;
+use super::literal::{self, Literal};
+use super::spanned::Spanned;
+
+/// An assembler directive, that is expanded into instructions while assembling.
+#[derive(Clone, Debug)]
+pub enum Directive {
+    /// `.string <index> "<text>"`
+    ///
+    /// Stores the characters of `text` in the data registers, starting at `Rx[index]`.
+    String {
+        index: Spanned<URS>,
+        string: Spanned<String>,
+    },
+    /// `.equ <name> <expression>` or `.const <name> <expression>`
+    ///
+    /// Defines a named constant, that can be used as an argument.
+    Equ {
+        name: Spanned<String>,
+        expr: Spanned<Expression>,
+    },
+    /// `.include "<path>"`
+    ///
+    /// Assembles another file in place of the directive.
+    Include {
+        path: Spanned<String>,
+    },
+    /// `.export <jump point>...`
+    ///
+    /// Allows other objects to use the jump points.
+    Export {
+        names: Vec<Spanned<String>>,
+    },
+    /// `.import <jump point>...`
+    ///
+    /// Uses jump points that are declared in other objects.
+    Import {
+        names: Vec<Spanned<String>>,
+    },
+    /// `.entry <jump point>`
+    ///
+    /// Starts the program at the jump point instead of the first instruction.
+    Entry {
+        name: Spanned<String>,
+    },
+}
+
+impl Directive {
+    pub const NAMES: &'static [&'static str] = &[".string", ".equ", ".const", ".include", ".export", ".import", ".entry"];
+
+    /// Returns whether the first token of a line is a directive.
+    pub fn is_directive(tokens: &[Spanned<&str>]) -> bool {
+        tokens
+            .first()
+            .map(|token| Self::NAMES.iter().any(|name| name.eq_ignore_ascii_case(token.value)))
+            .unwrap_or(false)
+    }
+
+    pub fn from_tokens(s: &str, tokens: &[Spanned<&str>]) -> Result<Self, Spanned<ParseError>> {
+        let name = &tokens[0];
+
+        match name.value.to_lowercase().as_str() {
+            ".string" => {
+                let (index, string) = match tokens {
+                    [_, index, string] => (index, string),
+                    _ => return Err(arguments_error(tokens, ".string <index> \"<text>\"")),
+                };
+
+                Ok(Self::String {
+                    index: parse_index(index)?,
+                    string: Spanned::new(
+                        literal::parse_string(string.value)
+                            .map_err(|err| Spanned::new(err, string.columns.clone()))?,
+                        string.columns.clone(),
+                    ),
+                })
+            }
+            name @ ".equ" | name @ ".const" => {
+                let (constant, first, last) = match tokens {
+                    [_, constant, first, .., last] => (constant, first, last),
+                    [_, constant, expr] => (constant, expr, expr),
+                    _ => return Err(arguments_error(tokens, &format!("{} <name> <value>", name))),
+                };
+
+                let is_instruction = constant.value
+                    .to_uppercase()
+                    .parse::<Instruction>()
+                    .is_ok();
+                if !Expression::is_identifier(constant.value) || is_instruction {
+                    return Err(Spanned::new(
+                        ParseError::InvalidConstantName { name: constant.value.to_owned() },
+                        constant.columns.clone(),
+                    ));
+                }
+
+                let columns = first.columns.start..last.columns.end;
+                let expr = Expression::parse(code_line::slice_columns(s, columns.clone()))
+                    .map_err(|err| Spanned::new(err, columns.clone()))?;
+
+                Ok(Self::Equ {
+                    name: Spanned::new(constant.value.to_owned(), constant.columns.clone()),
+                    expr: Spanned::new(expr, columns),
+                })
+            }
+            ".include" => {
+                let path = match tokens {
+                    [_, path] => path,
+                    _ => return Err(arguments_error(tokens, ".include \"<path>\"")),
+                };
+
+                Ok(Self::Include {
+                    path: Spanned::new(
+                        literal::parse_string(path.value)
+                            .map_err(|err| Spanned::new(err, path.columns.clone()))?,
+                        path.columns.clone(),
+                    ),
+                })
+            }
+            name @ ".export" | name @ ".import" => {
+                if tokens.len() < 2 {
+                    return Err(arguments_error(tokens, &format!("{} <jump point>...", name)));
+                }
+
+                let names = tokens[1..]
+                    .iter()
+                    .map(|token| match token.value.parse::<JumpPoint>() {
+                        Ok(JumpPoint(jp)) => Ok(Spanned::new(jp, token.columns.clone())),
+                        Err(()) => Err(Spanned::new(
+                            ParseError::ExpectedJumpPoint { token: token.value.to_owned() },
+                            token.columns.clone(),
+                        )),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                match name {
+                    ".export" => Ok(Self::Export { names }),
+                    _ => Ok(Self::Import { names }),
+                }
+            }
+            ".entry" => {
+                let token = match tokens {
+                    [_, token] => token,
+                    _ => return Err(arguments_error(tokens, ".entry <jump point>")),
+                };
+
+                match token.value.parse::<JumpPoint>() {
+                    Ok(JumpPoint(jp)) => Ok(Self::Entry { name: Spanned::new(jp, token.columns.clone()) }),
+                    Err(()) => Err(Spanned::new(
+                        ParseError::ExpectedJumpPoint { token: token.value.to_owned() },
+                        token.columns.clone(),
+                    )),
+                }
+            }
+            _ => unreachable!("`is_directive` only accepts known directives"),
+        }
+    }
+
+    /// Expands the directive into the code lines it stands for.
+    pub fn expand(&self, line: &Location) -> Result<Vec<CodeLine>, Spanned<Error>> {
+        match self {
+            Self::String { index, string } => {
+                let len = string.value.chars().count();
+                let start = index.value.min(DATA_REGISTERS as URS) as usize;
+                let available = DATA_REGISTERS - start;
+
+                if len > available {
+                    return Err(Spanned::new(
+                        Error::StringDoesNotFit { line: line.clone(), index: index.value, len, available },
+                        index.columns.start..string.columns.end,
+                    ));
+                }
+
+                let columns = string.columns.clone();
+                let token = |ct| Spanned::new(ct, columns.clone());
+
+                Ok(string.value
+                    .chars()
+                    .enumerate()
+                    .flat_map(|(i, c)| vec![
+                        CodeLine::DoubleToken(token(CodeToken::Inst(Instruction::DLOAD)), token(CodeToken::Val(c as IRS)), Addressing::Direct),
+                        CodeLine::DoubleToken(token(CodeToken::Inst(Instruction::STORE)), token(CodeToken::Val((start + i) as IRS)), Addressing::Direct),
+                    ])
+                    .collect())
+            }
+            Self::Equ { .. } | Self::Include { .. } | Self::Export { .. } | Self::Import { .. } |
+            Self::Entry { .. } => Ok(Vec::new()),
+        }
+    }
+}
+
+fn parse_index(token: &Spanned<&str>) -> Result<Spanned<URS>, Spanned<ParseError>> {
+    match Literal::parse(token.value) {
+        Ok(Some(Literal::Unsigned(index))) => Ok(Spanned::new(index, token.columns.clone())),
+        Ok(_) => Err(Spanned::new(
+            ParseError::InvalidDirectiveArgument { token: token.value.to_owned() },
+            token.columns.clone(),
+        )),
+        Err(err) => Err(Spanned::new(err, token.columns.clone())),
+    }
+}
+
+fn arguments_error(tokens: &[Spanned<&str>], usage: &str) -> Spanned<ParseError> {
+    Spanned::new(
+        ParseError::InvalidDirectiveArguments {
+            directive: tokens[0].value.to_owned(),
+            usage: usage.to_owned(),
+        },
+        tokens[0].columns.start..tokens[tokens.len() - 1].columns.end,
+    )
+}
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+
+use crate::IRS;
+use crate::error::{ExpressionError, ParseError};
+
+use super::literal::Literal;
+
+/// A constant expression like `NUMBER+1`, `'A'+2` or `.end-.start`, that is
+/// evaluated while assembling.
+#[derive(Clone, Debug, PartialEq, Eq, derive_more::Display)]
+pub enum Expression {
+    #[display(fmt = "{}", _0)]
+    Value(IRS),
+    #[display(fmt = "{}", _0)]
+    Constant(String),
+    #[display(fmt = ".{}", _0)]
+    JumpPoint(String),
+    #[display(fmt = "-{}", _0)]
+    Neg(Box<Expression>),
+    #[display(fmt = "({}{}{})", _1, _0, _2)]
+    Binary(Operator, Box<Expression>, Box<Expression>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, derive_more::Display)]
+pub enum Operator {
+    #[display(fmt = "+")]
+    Add,
+    #[display(fmt = "-")]
+    Sub,
+    #[display(fmt = "*")]
+    Mul,
+    #[display(fmt = "/")]
+    Div,
+    #[display(fmt = "%")]
+    Rem,
+}
+
+impl Expression {
+    pub fn parse(s: &str) -> Result<Self, ParseError> {
+        let mut parser = Parser { s, rest: s };
+        let expr = parser.parse_sum()?;
+
+        if parser.rest.trim_start().is_empty() {
+            Ok(expr)
+        } else {
+            Err(parser.error())
+        }
+    }
+
+    /// Returns whether a constant name is a valid identifier.
+    pub fn is_identifier(name: &str) -> bool {
+        let mut chars = name.chars();
+        matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_')
+            && chars.all(|c| c.is_alphanumeric() || c == '_')
+    }
+
+    /// All constants used in this expression.
+    pub fn constants(&self) -> Vec<&str> {
+        let mut constants = Vec::new();
+        self.visit(&mut |expr| if let Self::Constant(name) = expr {
+            constants.push(name.as_str());
+        });
+        constants
+    }
+
+    /// All jump points used in this expression.
+    pub fn jump_points(&self) -> Vec<&str> {
+        let mut jump_points = Vec::new();
+        self.visit(&mut |expr| if let Self::JumpPoint(name) = expr {
+            jump_points.push(name.as_str());
+        });
+        jump_points
+    }
+
+    /// Evaluates the expression.
+    ///
+    /// All constants and jump points have to be resolved already.
+    pub fn evaluate(&self, constants: &HashMap<String, IRS>, jump_points: &HashMap<String, IRS>) -> Result<IRS, ExpressionError> {
+        match self {
+            Self::Value(val) => Ok(*val),
+            Self::Constant(name) => constants
+                .get(name)
+                .copied()
+                .ok_or_else(|| ExpressionError::UndefinedConstant { name: name.clone() }),
+            Self::JumpPoint(name) => jump_points
+                .get(name)
+                .copied()
+                .ok_or_else(|| ExpressionError::UndefinedJumpPoint { name: name.clone() }),
+            Self::Neg(expr) => expr
+                .evaluate(constants, jump_points)?
+                .checked_neg()
+                .ok_or(ExpressionError::Overflow),
+            Self::Binary(op, lhs, rhs) => {
+                let lhs = lhs.evaluate(constants, jump_points)?;
+                let rhs = rhs.evaluate(constants, jump_points)?;
+
+                match op {
+                    Operator::Div | Operator::Rem if rhs == 0 => Err(ExpressionError::DivideByZero),
+                    Operator::Add => lhs.checked_add(rhs).ok_or(ExpressionError::Overflow),
+                    Operator::Sub => lhs.checked_sub(rhs).ok_or(ExpressionError::Overflow),
+                    Operator::Mul => lhs.checked_mul(rhs).ok_or(ExpressionError::Overflow),
+                    Operator::Div => lhs.checked_div(rhs).ok_or(ExpressionError::Overflow),
+                    Operator::Rem => lhs.checked_rem(rhs).ok_or(ExpressionError::Overflow),
+                }
+            }
+        }
+    }
+
+    /// How often each jump point is added to the value, `.a+.a-.b` adds `.a` twice and `.b` -1 times.
+    ///
+    /// `constant` looks up the expressions of constants. Fails if a jump point is multiplied,
+    /// divided or used in a remainder, since the value then does not move together with it.
+    pub fn jump_point_weights<'c, F: Fn(&str) -> Option<&'c Expression>>(&self, constant: &F) -> Option<HashMap<String, IRS>> {
+        let mut weights = HashMap::new();
+        self.add_jump_point_weights(1, constant, &mut Vec::new(), &mut weights)?;
+        weights.retain(|_, weight| *weight != 0);
+        Some(weights)
+    }
+
+    fn add_jump_point_weights<'c, F: Fn(&str) -> Option<&'c Expression>>(
+        &self,
+        weight: IRS,
+        constant: &F,
+        stack: &mut Vec<String>,
+        weights: &mut HashMap<String, IRS>,
+    ) -> Option<()> {
+        match self {
+            Self::Value(_) => {}
+            Self::JumpPoint(name) => *weights.entry(name.clone()).or_default() += weight,
+            Self::Constant(name) => {
+                // cycles are reported when the constants are evaluated
+                if stack.contains(name) {
+                    return None;
+                }
+                stack.push(name.clone());
+                constant(name)?.add_jump_point_weights(weight, constant, stack, weights)?;
+                stack.pop();
+            }
+            Self::Neg(expr) => expr.add_jump_point_weights(-weight, constant, stack, weights)?,
+            Self::Binary(Operator::Add, lhs, rhs) => {
+                lhs.add_jump_point_weights(weight, constant, stack, weights)?;
+                rhs.add_jump_point_weights(weight, constant, stack, weights)?;
+            }
+            Self::Binary(Operator::Sub, lhs, rhs) => {
+                lhs.add_jump_point_weights(weight, constant, stack, weights)?;
+                rhs.add_jump_point_weights(-weight, constant, stack, weights)?;
+            }
+            // fine as long as the jump points cancel out, like in `(.end-.start)/2`
+            Self::Binary(_, lhs, rhs) => {
+                for expr in [lhs, rhs].iter() {
+                    let mut inner = HashMap::new();
+                    expr.add_jump_point_weights(1, constant, stack, &mut inner)?;
+                    if inner.values().any(|&weight| weight != 0) {
+                        return None;
+                    }
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// Renames all jump points for which `rename` returns a new name.
+    pub fn rename_jump_points<F: Fn(&str) -> Option<String>>(&mut self, rename: &F) {
+        match self {
+            Self::JumpPoint(name) => {
+                if let Some(new) = rename(name) {
+                    *name = new;
+                }
+            }
+            Self::Neg(expr) => expr.rename_jump_points(rename),
+            Self::Binary(_, lhs, rhs) => {
+                lhs.rename_jump_points(rename);
+                rhs.rename_jump_points(rename);
+            }
+            Self::Value(_) | Self::Constant(_) => {}
+        }
+    }
+
+    fn visit<'a, F: FnMut(&'a Self)>(&'a self, f: &mut F) {
+        f(self);
+        match self {
+            Self::Neg(expr) => expr.visit(f),
+            Self::Binary(_, lhs, rhs) => {
+                lhs.visit(f);
+                rhs.visit(f);
+            }
+            Self::Value(_) | Self::Constant(_) | Self::JumpPoint(_) => {}
+        }
+    }
+}
+
+/// A recursive descent parser for constant expressions.
+struct Parser<'a> {
+    s: &'a str,
+    rest: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn parse_sum(&mut self) -> Result<Expression, ParseError> {
+        let mut lhs = self.parse_product()?;
+
+        loop {
+            let op = match self.peek() {
+                Some('+') => Operator::Add,
+                Some('-') => Operator::Sub,
+                _ => return Ok(lhs),
+            };
+            self.bump();
+
+            let rhs = self.parse_product()?;
+            lhs = Expression::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_product(&mut self) -> Result<Expression, ParseError> {
+        let mut lhs = self.parse_unary()?;
+
+        loop {
+            let op = match self.peek() {
+                Some('*') => Operator::Mul,
+                Some('/') => Operator::Div,
+                Some('%') => Operator::Rem,
+                _ => return Ok(lhs),
+            };
+            self.bump();
+
+            let rhs = self.parse_unary()?;
+            lhs = Expression::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, ParseError> {
+        match self.peek() {
+            Some('-') => {
+                self.bump();
+                // fold negative literals, so `-9223372036854775808` does not overflow
+                match self.parse_unary()? {
+                    Expression::Value(val) => Ok(Expression::Value(val.wrapping_neg())),
+                    expr => Ok(Expression::Neg(Box::new(expr))),
+                }
+            }
+            Some('(') => {
+                self.bump();
+                let expr = self.parse_sum()?;
+                match self.peek() {
+                    Some(')') => {
+                        self.bump();
+                        Ok(expr)
+                    }
+                    _ => Err(self.error()),
+                }
+            }
+            Some('\'') => {
+                let len = char_literal_len(self.rest).ok_or_else(|| self.error())?;
+                let literal = self.take(len);
+                match Literal::parse(literal)? {
+                    Some(Literal::Unsigned(val)) => Ok(Expression::Value(val as IRS)),
+                    Some(Literal::Signed(val)) => Ok(Expression::Value(val)),
+                    None => Err(self.error()),
+                }
+            }
+            Some('.') => {
+                self.bump();
+                let name = self.take_identifier();
+                if name.is_empty() {
+                    return Err(self.error());
+                }
+                Ok(Expression::JumpPoint(name.to_owned()))
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let literal = self.take_identifier();
+                match Literal::parse(literal)? {
+                    Some(Literal::Unsigned(val)) => Ok(Expression::Value(val as IRS)),
+                    Some(Literal::Signed(val)) => Ok(Expression::Value(val)),
+                    None => Err(self.error()),
+                }
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                Ok(Expression::Constant(self.take_identifier().to_owned()))
+            }
+            _ => Err(self.error()),
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.rest = self.rest.trim_start();
+        self.rest.chars().next()
+    }
+
+    fn bump(&mut self) {
+        let mut chars = self.rest.chars();
+        chars.next();
+        self.rest = chars.as_str();
+    }
+
+    fn take(&mut self, len: usize) -> &'a str {
+        let (taken, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        taken
+    }
+
+    fn take_identifier(&mut self) -> &'a str {
+        let len = self.rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(self.rest.len());
+        self.take(len)
+    }
+
+    fn error(&self) -> ParseError {
+        ParseError::InvalidExpression {
+            expr: self.s.to_owned(),
+            at: self.s.len() - self.rest.len(),
+        }
+    }
+}
+
+/// The length of the character literal at the start of `s`, including both quotes.
+fn char_literal_len(s: &str) -> Option<usize> {
+    let mut escaped = false;
+
+    for (i, c) in s.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '\'' => return Some(i + 1),
+            _ => {}
+        }
+    }
+
+    None
+}
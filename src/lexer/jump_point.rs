@@ -13,8 +13,12 @@ impl FromStr for JumpPoint {
     type Err = ();
 
     fn from_str(s: &str) -> crate::Result<Self, Self::Err> {
-        if !(s.starts_with('.') && !s.ends_with(':') && s.chars().count() > 1) {
-            return Err(());
+        let is_name = |name: &str| !name.is_empty() && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_');
+
+        if !(s.starts_with('.') && is_name(&s[1..])) {
+            Err(())
         } else {
             s
                 .strip_prefix('.')
@@ -0,0 +1,129 @@
+use std::num::IntErrorKind;
+
+use crate::{IRS, URS};
+use crate::error::ParseError;
+
+/// A numeric literal, either a number in decimal, hex (`0x48`), binary (`0b1001000`),
+/// or octal (`0o110`) notation, or a character literal (`'H'`, `'\n'`).
+///
+/// Numbers may be negative and contain `_` as digit separators. Decimal numbers have to fit
+/// into [`IRS`], while hex, binary and octal numbers may use all bits of [`URS`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Literal {
+    Unsigned(URS),
+    Signed(IRS),
+}
+
+impl Literal {
+    /// Parses a literal.
+    ///
+    /// Returns `Ok(None)` if `s` does not look like a literal at all, so other kinds of
+    /// tokens can be tried, and an error if it looks like a literal but is malformed.
+    pub fn parse(s: &str) -> Result<Option<Self>, ParseError> {
+        if s.starts_with('\'') {
+            return parse_char(s).map(|c| Some(Self::Unsigned(c as URS)));
+        }
+
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        if !digits.starts_with(|c: char| c.is_ascii_digit()) {
+            return Ok(None);
+        }
+
+        let (radix, digits) = match digits.get(..2).map(str::to_ascii_lowercase).as_deref() {
+            Some("0x") => (16, &digits[2..]),
+            Some("0b") => (2, &digits[2..]),
+            Some("0o") => (8, &digits[2..]),
+            _ => (10, digits),
+        };
+
+        let invalid = || ParseError::InvalidLiteral { literal: s.to_owned() };
+        if digits.is_empty() || digits.starts_with('_') || digits.ends_with('_') {
+            return Err(invalid());
+        }
+
+        let digits = digits.replace('_', "");
+        let value = u128::from_str_radix(&digits, radix)
+            .map_err(|err| match err.kind() {
+                IntErrorKind::PosOverflow => ParseError::LiteralOverflow { literal: s.to_owned() },
+                _ => invalid(),
+            })?;
+
+        if negative {
+            if value > IRS::MIN.unsigned_abs() as u128 {
+                return Err(ParseError::LiteralOverflow { literal: s.to_owned() });
+            }
+            Ok(Some(Self::Signed((value as i128).wrapping_neg() as IRS)))
+        } else if value > URS::MAX as u128 || (radix == 10 && value > IRS::MAX as u128) {
+            // only bit patterns like `0xffff_ffff_ffff_ffff` may use the sign bit
+            Err(ParseError::LiteralOverflow { literal: s.to_owned() })
+        } else {
+            Ok(Some(Self::Unsigned(value as URS)))
+        }
+    }
+}
+
+fn parse_char(s: &str) -> Result<char, ParseError> {
+    let invalid = || ParseError::InvalidLiteral { literal: s.to_owned() };
+
+    let inner = s
+        .strip_prefix('\'')
+        .and_then(|s| s.strip_suffix('\''))
+        .ok_or_else(invalid)?;
+
+    let mut chars = inner.chars();
+    let c = match chars.next() {
+        Some('\\') => parse_escape(&mut chars).ok_or_else(invalid)?,
+        Some(c) => c,
+        None => return Err(invalid()),
+    };
+
+    match chars.next() {
+        Some(_) => Err(invalid()),
+        None => Ok(c),
+    }
+}
+
+/// Parses the escape sequence following a `\`.
+pub(crate) fn parse_escape(chars: &mut std::str::Chars) -> Option<char> {
+    match chars.next()? {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        '0' => Some('\0'),
+        '\\' => Some('\\'),
+        '\'' => Some('\''),
+        '"' => Some('"'),
+        'x' => {
+            let hex = chars.as_str().get(..2).filter(|hex| hex.chars().all(|c| c.is_ascii_hexdigit()))?;
+            let c = u8::from_str_radix(hex, 16).ok()?;
+            chars.nth(1);
+            Some(c as char)
+        }
+        _ => None,
+    }
+}
+
+/// Parses a string literal like `"Hello World!\n"`.
+pub fn parse_string(s: &str) -> Result<String, ParseError> {
+    let invalid = || ParseError::InvalidString { literal: s.to_owned() };
+
+    let inner = s
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(invalid)?;
+
+    let mut string = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => string.push(parse_escape(&mut chars).ok_or_else(invalid)?),
+            '"' => return Err(invalid()),
+            c => string.push(c),
+        }
+    }
+
+    Ok(string)
+}
@@ -0,0 +1,161 @@
+use std::collections::HashSet;
+
+use crate::diagnostic::Span;
+use crate::error::ParseError;
+use crate::instruction::Instruction;
+use crate::source::Location;
+
+use super::code_line::{self, Quotes};
+use super::expression::Expression;
+use super::jump_point::JumpPoint;
+use super::spanned::Spanned;
+
+/// How deep macro expansions may be nested, to stop endless recursion.
+pub const MAX_MACRO_DEPTH: usize = 64;
+
+/// A parameterized macro, defined by
+///
+/// ```text
+/// .macro <name> [<parameter>...]
+///     <body>
+/// .endm
+/// ```
+///
+/// Calling the macro with `<name> [<argument>...]` replaces the call with the body,
+/// where every parameter is replaced with its argument.
+/// Jump points declared in the body are local to each expansion.
+#[derive(Clone, Debug)]
+pub struct Macro {
+    pub name: Spanned<String>,
+    pub line: Location,
+    pub params: Vec<String>,
+    /// The lines of the body together with their line numbers.
+    pub body: Vec<(Location, String)>,
+}
+
+impl Macro {
+    /// Returns whether a line starts a macro definition.
+    pub fn is_start(tokens: &[Spanned<&str>]) -> bool {
+        is_keyword(tokens, ".macro")
+    }
+
+    /// Returns whether a line ends a macro definition.
+    pub fn is_end(tokens: &[Spanned<&str>]) -> bool {
+        is_keyword(tokens, ".endm")
+    }
+
+    /// Parses the first line of a macro definition, the body is added later.
+    pub fn from_tokens(line: Location, tokens: &[Spanned<&str>]) -> Result<Self, Spanned<ParseError>> {
+        let name = match tokens.get(1) {
+            Some(name) => name,
+            None => return Err(Spanned::new(
+                ParseError::InvalidDirectiveArguments {
+                    directive: tokens[0].value.to_owned(),
+                    usage: ".macro <name> [<parameter>...]".to_owned(),
+                },
+                tokens[0].columns.clone(),
+            )),
+        };
+        if !is_valid_name(name.value) {
+            return Err(Spanned::new(
+                ParseError::InvalidMacroName { name: name.value.to_owned() },
+                name.columns.clone(),
+            ));
+        }
+
+        let mut params = Vec::<String>::new();
+        for param in tokens[2..].iter() {
+            if !is_valid_name(param.value) || params.iter().any(|p| p == param.value) {
+                return Err(Spanned::new(
+                    ParseError::InvalidMacroParameter { name: param.value.to_owned() },
+                    param.columns.clone(),
+                ));
+            }
+            params.push(param.value.to_owned());
+        }
+
+        Ok(Self {
+            name: Spanned::new(name.value.to_owned(), name.columns.clone()),
+            line,
+            params,
+            body: Vec::new(),
+        })
+    }
+
+    pub fn span(&self) -> Span {
+        Span::new(self.line.clone(), self.name.columns.clone())
+    }
+
+    /// The names of all jump points declared in the body.
+    pub fn local_jump_points(&self) -> HashSet<String> {
+        self.body
+            .iter()
+            .filter_map(|(_, line)| match code_line::split_tokens(line).as_slice() {
+                [token] => token.value
+                    .strip_suffix(':')
+                    .and_then(|jp| jp.parse::<JumpPoint>().ok())
+                    .map(|JumpPoint(name)| name),
+                _ => None
+            })
+            .collect()
+    }
+
+    /// Replaces all parameters in a line of the body with their arguments.
+    ///
+    /// Parameters inside of quotes, comments, jump points and other words are left alone.
+    pub fn substitute(&self, line: &str, args: &[&str]) -> String {
+        let mut out = String::with_capacity(line.len());
+        let mut quotes = Quotes::default();
+        let mut chars = line.char_indices().peekable();
+        let mut prev = None;
+
+        while let Some((i, c)) = chars.next() {
+            if c == ';' && !quotes.in_quotes() {
+                out.push_str(&line[i..]);
+                break;
+            }
+
+            let starts_word = (c.is_alphabetic() || c == '_')
+                && !matches!(prev, Some(p) if p == '.' || p == '_' || p == '\\' || char::is_alphanumeric(p));
+            if quotes.in_quotes() || !starts_word {
+                quotes.push(c);
+                out.push(c);
+                prev = Some(c);
+                continue;
+            }
+
+            let mut end = i + c.len_utf8();
+            while let Some(&(j, next)) = chars.peek() {
+                if !(next.is_alphanumeric() || next == '_') {
+                    break;
+                }
+                end = j + next.len_utf8();
+                prev = Some(next);
+                chars.next();
+            }
+            if end == i + c.len_utf8() {
+                prev = Some(c);
+            }
+
+            let word = &line[i..end];
+            match self.params.iter().position(|param| param == word) {
+                Some(index) => out.push_str(args[index]),
+                None => out.push_str(word),
+            }
+        }
+
+        out
+    }
+}
+
+/// Macro names and parameters have to be identifiers, that are no instruction names.
+fn is_valid_name(name: &str) -> bool {
+    Expression::is_identifier(name) && name.to_uppercase().parse::<Instruction>().is_err()
+}
+
+fn is_keyword(tokens: &[Spanned<&str>], keyword: &str) -> bool {
+    tokens
+        .first()
+        .map(|token| token.value.eq_ignore_ascii_case(keyword))
+        .unwrap_or(false)
+}
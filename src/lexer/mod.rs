@@ -1,21 +1,52 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
 
 use code_token::CodeToken;
 
-use crate::{Error, IRS, RAM, Result};
+use crate::{Error, IRS, RAM, Result, URS};
+use crate::diagnostic::{Diagnostic, Diagnostics, Span};
+use crate::image::Image;
 use crate::instruction::Instruction;
+use crate::listing::{Listing, Word};
+use crate::object::{Object, Relocation};
+use crate::error::{ExpressionError, Warning};
 use crate::lexer::code_line::CodeLine;
+use crate::lexer::constant::Constant;
+use crate::lexer::directive::Directive;
 use crate::lexer::jump_point::JumpPoint;
+use crate::lexer::macros::{Macro, MAX_MACRO_DEPTH};
+use crate::lexer::options::Options;
+use crate::lexer::spanned::Spanned;
+use crate::source::{InMemory, Location, SourceProvider};
+use crate::source_map::SourceMap;
 
 pub mod code_token;
 pub mod code_line;
+pub mod constant;
+pub mod directive;
+pub mod expression;
 pub mod jump_point;
+pub mod literal;
+pub mod macros;
+pub mod options;
+pub mod spanned;
 
-type CodeLineIndex = usize;
+type CodeLineIndex = Location;
 
 #[derive(Debug)]
 pub struct Document {
-    code_lines: Vec<(CodeLineIndex, CodeLine)>
+    code_lines: Vec<(CodeLineIndex, CodeLine)>,
+    jump_points: HashMap<String, usize>,
+    constants: Vec<Constant>,
+    /// Jump points that other objects may use.
+    exports: Vec<(Span, String)>,
+    /// Jump points that are declared in other objects.
+    imports: Vec<(Span, String)>,
+    /// The jump point the program starts at.
+    entry: Option<(Span, String)>,
+    relocations: Vec<Relocation>,
+    /// Expressions that depend on where the object ends up in RAM, but can not be relocated.
+    unrelocatable: Vec<(Location, String)>,
 }
 
 impl Document {
@@ -26,83 +57,815 @@ impl Document {
             .collect()
     }
 
+    /// A relocatable object, that can be linked together with other objects.
+    ///
+    /// Fails if an expression uses jump points in a way that can not be relocated, like `.loop*2`.
+    pub fn object(&self) -> Result<Object> {
+        if let Some((line, expr)) = self.unrelocatable.first() {
+            return Err(Error::NotRelocatable { expr: expr.clone(), line: line.clone() });
+        }
+
+        Ok(Object {
+            code: self.as_ram(),
+            exports: self.exports
+                .iter()
+                .filter_map(|(_, name)| self.jump_points.get(name).map(|&addr| (name.clone(), addr as URS)))
+                .collect(),
+            imports: self.imports
+                .iter()
+                .map(|(_, name)| name.clone())
+                .collect(),
+            relocations: self.relocations.clone(),
+            entry: self.entry(),
+        })
+    }
+
+    /// A program image with all jump points as symbols and the source map.
+    pub fn image(&self) -> Image {
+        Image {
+            entry: self.entry().unwrap_or(0),
+            ram: self.as_ram(),
+            symbols: Some(self.jump_points
+                .iter()
+                .map(|(name, &addr)| (name.clone(), addr as URS))
+                .collect()),
+            source_map: Some(self.source_map()),
+        }
+    }
+
+    /// The address the program starts at, if it is set with `.entry`.
+    pub fn entry(&self) -> Option<URS> {
+        self.entry
+            .as_ref()
+            .and_then(|(_, name)| self.jump_points.get(name))
+            .map(|&addr| addr as URS)
+    }
+
+    /// Whether the document uses jump points of other objects, and has to be linked.
+    pub fn has_imports(&self) -> bool {
+        !self.imports.is_empty()
+    }
+
+    pub fn source_map(&self) -> SourceMap {
+        let lines = self.code_lines
+            .iter()
+            .map(|(i, _)| i.clone())
+            .collect();
+
+        let mut labels = BTreeMap::<URS, Vec<String>>::new();
+        for (name, &addr) in self.jump_points.iter() {
+            labels.entry(addr as URS).or_default().push(name.clone());
+        }
+        labels.values_mut().for_each(|names| names.sort());
+
+        SourceMap::new(lines, labels)
+    }
+
+    /// A listing of the document, with every source line next to the words it was assembled to.
+    ///
+    /// `source` is the source code of the main file, the source code of included files is
+    /// taken from the `diagnostics` of [`Document::assemble_with`].
+    pub fn listing(&self, source: &str, diagnostics: &Diagnostics) -> Listing {
+        let source_map = self.source_map();
+        let target = |addr: URS, val: IRS| match self.relocations.iter().find(|r| r.address == addr)?.symbol {
+            Some(ref symbol) => Some(symbol.clone()),
+            None => source_map.labels(val as URS).first().cloned(),
+        };
+
+        let words = self.code_lines
+            .iter()
+            .enumerate()
+            .map(|(addr, (i, cl))| {
+                let (inst, val) = cl.as_urs_irs();
+                let address = addr as URS;
+                (i.clone(), Word { address, inst, val, target: target(address, val) })
+            })
+            .collect();
+
+        let mut jump_points = self.jump_points
+            .iter()
+            .map(|(name, &addr)| (name.clone(), addr as URS))
+            .collect::<Vec<_>>();
+        jump_points.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+
+        Listing {
+            rows: Listing::rows(words, |file| match file {
+                Some(file) => diagnostics.source(file),
+                None => Some(source),
+            }),
+            jump_points,
+            constants: self.constants
+                .iter()
+                .filter_map(|c| Some((c.name.value.clone(), c.value?)))
+                .collect(),
+            imports: self.imports
+                .iter()
+                .map(|(_, name)| name.clone())
+                .collect(),
+        }
+    }
+
     pub fn from_str(s: &str) -> Result<Self> {
-        let mut doc = Self::parse(s)?;
+        let (doc, diagnostics) = Self::from_str_with_diagnostics(s);
+        doc.ok_or(Error::Assembly(diagnostics))
+    }
+
+    /// Assembles a document and collects every problem found on the way,
+    /// instead of stopping at the first one.
+    ///
+    /// The document is only returned if there were no errors.
+    pub fn from_str_with_diagnostics(s: &str) -> (Option<Self>, Diagnostics) {
+        Self::assemble(s, &Options::default())
+    }
+
+    /// Same as [`Document::from_str_with_diagnostics`], but with custom [`Options`].
+    ///
+    /// Files can't be included, use [`Document::assemble_with`] for that.
+    pub fn assemble(s: &str, options: &Options) -> (Option<Self>, Diagnostics) {
+        Self::assemble_with(s, None, &InMemory::default(), options)
+    }
 
-        doc.check()?;
-        doc.resolve_jump_points()?;
+    /// Assembles a document, that may include other files loaded by `provider`.
+    ///
+    /// `path` is the resolved path of the main file if it has one, to notice when it includes itself.
+    pub fn assemble_with(
+        s: &str,
+        path: Option<&str>,
+        provider: &dyn SourceProvider,
+        options: &Options,
+    ) -> (Option<Self>, Diagnostics) {
+        let mut diagnostics = Diagnostics::default();
+        let mut context = Context {
+            provider,
+            macros: Macros::default(),
+            includes: path.map(str::to_owned).into_iter().collect(),
+            included: HashSet::new(),
+        };
 
-        Ok(doc)
+        let mut doc = Self {
+            code_lines: Vec::new(),
+            jump_points: HashMap::new(),
+            constants: Vec::new(),
+            exports: Vec::new(),
+            imports: Vec::new(),
+            entry: None,
+            relocations: Vec::new(),
+            unrelocatable: Vec::new(),
+        };
+        doc.parse_file(None, s, &mut context, &mut diagnostics);
+        doc.resolve_jump_points(options, &mut diagnostics);
+        doc.evaluate_expressions(&mut diagnostics);
+
+        diagnostics.sort();
+        if diagnostics.has_errors() {
+            (None, diagnostics)
+        } else {
+            (Some(doc), diagnostics)
+        }
     }
 
-    fn parse(s: &str) -> Result<Self> {
-        let mut code_lines = Vec::new();
+    /// Parses all lines of a file, and collects the macros defined in it.
+    fn parse_file(&mut self, file: Option<&str>, s: &str, context: &mut Context, diagnostics: &mut Diagnostics) {
+        // the start of the current macro definition, and the macro if its first line is valid
+        let mut definition: Option<(Location, Option<Macro>)> = None;
+        let file = file.map(Arc::from);
 
         for (i, line) in s.lines().enumerate() {
-            let line_i = i + 1;
+            let location = Location::new(file.clone(), i + 1);
+            let tokens = code_line::split_tokens(line);
 
-            let code_line = CodeLine::from_str(line)
-                .map_err(|err| Error::ParsingFailed { s: line.to_owned(), line: line_i, err })?;
+            if let Some((ref start, ref mut mac)) = definition {
+                if Macro::is_end(&tokens) {
+                    if let Some(mac) = mac.take() {
+                        context.macros.define(mac, diagnostics);
+                    }
+                    definition = None;
+                } else if Macro::is_start(&tokens) {
+                    let outer = mac
+                        .as_ref()
+                        .map(|mac| mac.name.value.clone())
+                        .unwrap_or_else(|| format!("in line {}", start));
+                    let mut diagnostic = Diagnostic::error(
+                        Span::new(location.clone(), tokens[0].columns.clone()),
+                        Error::NestedMacro { line: location, outer },
+                    );
+                    if let Some(mac) = mac {
+                        diagnostic = diagnostic.with_related(mac.span(), "the outer macro starts here");
+                    }
+                    diagnostics.push(diagnostic);
+                } else if let Some(mac) = mac {
+                    mac.body.push((location, line.to_owned()));
+                }
+                continue;
+            }
 
-            if let Some(code_line) = code_line {
-                code_lines.push((line_i, code_line));
+            if Macro::is_start(&tokens) {
+                match Macro::from_tokens(location.clone(), &tokens) {
+                    Ok(mac) => definition = Some((location, Some(mac))),
+                    Err(Spanned { value: err, columns }) => {
+                        diagnostics.push_error(
+                            Span::new(location.clone(), columns),
+                            Error::ParsingFailed { s: line.to_owned(), line: location.clone(), err },
+                        );
+                        definition = Some((location, None));
+                    }
+                }
+                continue;
+            }
+            if Macro::is_end(&tokens) {
+                diagnostics.push_error(
+                    Span::new(location.clone(), tokens[0].columns.clone()),
+                    Error::UnexpectedEndMacro { line: location },
+                );
+                continue;
             }
+
+            self.parse_line(line, &location, context, 0, diagnostics);
         }
 
-        Ok(Self {
-            code_lines
-        })
+        if let Some((_, Some(mac))) = definition {
+            diagnostics.push_error(
+                mac.span(),
+                Error::UnclosedMacro { name: mac.name.value.clone(), line: mac.line },
+            );
+        }
     }
 
-    fn check(&self) -> Result<()> {
-        for &(i, ref code_line) in self.code_lines.iter() {
-            code_line
-                .check()
-                .map_err(|err| Error::InvalidTokenArrangement {
-                    line: i,
-                    err,
-                })?;
+    /// Parses a single line, and expands directives and macros.
+    ///
+    /// Returns `false` if a macro expansion was nested too deeply,
+    /// and all enclosing expansions should stop.
+    fn parse_line(&mut self, line: &str, location: &Location, context: &mut Context, depth: usize, diagnostics: &mut Diagnostics) -> bool {
+        let tokens = code_line::split_tokens(line);
+
+        if let Some(mac) = tokens.first().and_then(|token| context.macros.get(token.value)) {
+            let mac = mac.clone();
+            return self.expand_macro(&mac, location, &tokens, context, depth, diagnostics);
+        }
+
+        if Directive::is_directive(&tokens) {
+            let directive = match Directive::from_tokens(line, &tokens) {
+                Ok(directive) => directive,
+                Err(Spanned { value: err, columns }) => {
+                    diagnostics.push_error(
+                        Span::new(location.clone(), columns),
+                        Error::ParsingFailed { s: line.to_owned(), line: location.clone(), err },
+                    );
+                    return true;
+                }
+            };
+
+            match directive {
+                Directive::Equ { name, expr } => {
+                    let constant = Constant { name, line: location.clone(), expr, value: None };
+                    match self.constants.iter().find(|c| c.name.value == constant.name.value) {
+                        Some(first) => diagnostics.push(
+                            Diagnostic::error(
+                                constant.span(),
+                                Error::DuplicateConstant {
+                                    name: constant.name.value.clone(),
+                                    line: location.clone(),
+                                    first_line: first.line.clone(),
+                                },
+                            )
+                                .with_related(first.span(), "first defined here")
+                        ),
+                        None => self.constants.push(constant),
+                    }
+                }
+                Directive::Include { path } => self.include(path, location, context, diagnostics),
+                Directive::Export { names } => self.exports.extend(names
+                    .into_iter()
+                    .map(|name| (Span::new(location.clone(), name.columns), name.value))),
+                Directive::Import { names } => self.imports.extend(names
+                    .into_iter()
+                    .map(|name| (Span::new(location.clone(), name.columns), name.value))),
+                Directive::Entry { name } => {
+                    let span = Span::new(location.clone(), name.columns);
+                    match self.entry {
+                        Some((ref first, _)) => diagnostics.push(
+                            Diagnostic::error(
+                                span,
+                                Error::DuplicateEntry { line: location.clone(), first_line: first.location.clone() },
+                            )
+                                .with_related(first.clone(), "first set here")
+                        ),
+                        None => self.entry = Some((span, name.value)),
+                    }
+                }
+                directive => match directive.expand(location) {
+                    Ok(expanded) => self.code_lines.extend(expanded.into_iter().map(|cl| (location.clone(), cl))),
+                    Err(Spanned { value: err, columns }) => {
+                        diagnostics.push_error(Span::new(location.clone(), columns), err)
+                    }
+                },
+            }
+            return true;
         }
 
-        Ok(())
+        match CodeLine::from_tokens(line, &tokens) {
+            Ok(Some(code_line)) => {
+                if let Err(Spanned { value: err, columns }) = code_line.check() {
+                    diagnostics.push_error(
+                        Span::new(location.clone(), columns),
+                        Error::InvalidTokenArrangement { line: location.clone(), err },
+                    );
+                }
+                self.code_lines.push((location.clone(), code_line));
+            }
+            Ok(None) => {}
+            Err(Spanned { value: err, columns }) => diagnostics.push_error(
+                Span::new(location.clone(), columns),
+                Error::ParsingFailed { s: line.to_owned(), line: location.clone(), err },
+            ),
+        }
+        true
     }
 
-    fn resolve_jump_points(&mut self) -> Result<()> {
-        let jump_point_declarations = self.get_jump_point_declarations();
+    /// Parses an included file in place of the `.include` directive.
+    ///
+    /// Every file is only included once, later includes of the same file are ignored.
+    fn include(&mut self, path: Spanned<String>, location: &Location, context: &mut Context, diagnostics: &mut Diagnostics) {
+        let span = Span::new(location.clone(), path.columns.clone());
+        let resolved = context.provider.resolve(&path.value, location.file.as_deref());
 
-        for &mut (i, ref mut cl) in self.code_lines.iter_mut() {
-            if let CodeLine::DoubleToken(_, ct @ CodeToken::JumpPoint(_)) = cl {
-                let val;
-                match ct {
-                    CodeToken::JumpPoint(jp) => {
-                        val = jump_point_declarations.get(jp.as_ref())
-                            .ok_or(Error::UndefinedJumpPoint { name: jp.as_ref().to_owned(), line: i })?;
-                    }
+        if let Some(start) = context.includes.iter().position(|file| *file == resolved) {
+            let cycle = context.includes[start..]
+                .iter()
+                .chain(std::iter::once(&resolved))
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            diagnostics.push_error(span, Error::IncludeCycle { path: resolved, line: location.clone(), cycle });
+            return;
+        }
+        if !context.included.insert(resolved.clone()) {
+            return;
+        }
+
+        let source = match context.provider.load(&resolved) {
+            Ok(source) => source,
+            Err(err) => {
+                diagnostics.push_error(span, Error::IncludeFailed { path: resolved, line: location.clone(), err });
+                return;
+            }
+        };
+        diagnostics.add_source(resolved.clone(), source.clone());
+
+        context.includes.push(resolved.clone());
+        self.parse_file(Some(&resolved), &source, context, diagnostics);
+        context.includes.pop();
+    }
+
+    /// Replaces a macro call with the body of the macro.
+    ///
+    /// Problems inside of the body are reported at the call site,
+    /// and point to the line of the body they come from.
+    fn expand_macro(
+        &mut self,
+        mac: &Macro,
+        location: &Location,
+        tokens: &[Spanned<&str>],
+        context: &mut Context,
+        depth: usize,
+        diagnostics: &mut Diagnostics,
+    ) -> bool {
+        let columns = tokens[0].columns.start..tokens[tokens.len() - 1].columns.end;
+        let call = Span::new(location.clone(), columns.clone());
+
+        let args = tokens[1..]
+            .iter()
+            .map(|token| token.value)
+            .collect::<Vec<_>>();
+        if args.len() != mac.params.len() {
+            diagnostics.push(
+                Diagnostic::error(
+                    call,
+                    Error::MacroArgumentCount {
+                        name: mac.name.value.clone(),
+                        line: location.clone(),
+                        expected: mac.params.len(),
+                        found: args.len(),
+                    },
+                )
+                    .with_related(mac.span(), format!("`{}` is defined here", mac.name.value))
+            );
+            return true;
+        }
+
+        if depth >= MAX_MACRO_DEPTH {
+            diagnostics.push(
+                Diagnostic::error(
+                    call,
+                    Error::MacroRecursionLimit {
+                        name: mac.name.value.clone(),
+                        line: location.clone(),
+                        limit: MAX_MACRO_DEPTH,
+                    },
+                )
+                    .with_related(mac.span(), format!("`{}` is defined here", mac.name.value))
+            );
+            return false;
+        }
+
+        let local_jump_points = mac.local_jump_points();
+        let expansion = context.macros.next_expansion();
+        let rename = |name: &str| if local_jump_points.contains(name) {
+            Some(format!("{}@{}#{}", name, mac.name.value, expansion))
+        } else {
+            None
+        };
+
+        let mut complete = true;
+        for (body_location, body_line) in mac.body.iter() {
+            let expanded = mac.substitute(body_line, &args);
+            let first = self.code_lines.len();
+            let mut body_diagnostics = Diagnostics::default();
+
+            complete = self.parse_line(&expanded, location, context, depth + 1, &mut body_diagnostics);
+
+            for (_, cl) in self.code_lines[first..].iter_mut() {
+                cl.rename_jump_points(&rename);
+                cl.set_columns(columns.clone());
+            }
+            for mut diagnostic in body_diagnostics {
+                let span = Span::line(body_location.clone());
+                diagnostic.span = call.clone();
+                // recursive macros would repeat the same line over and over
+                if !diagnostic.related.iter().any(|related| related.span == span) {
+                    diagnostic = diagnostic.with_related(
+                        span,
+                        format!("in this line of `{}`, called in line {}", mac.name.value, location),
+                    );
+                }
+                diagnostics.push(diagnostic);
+            }
+
+            if !complete {
+                break;
+            }
+        }
+
+        complete
+    }
+
+    fn resolve_jump_points(&mut self, options: &Options, diagnostics: &mut Diagnostics) {
+        let jump_point_declarations = self.get_jump_point_declarations(options, diagnostics);
+        let mut used = HashSet::new();
+
+        let mut imports = HashMap::<&str, &Span>::new();
+        for (span, name) in self.imports.iter() {
+            if let Some((_, declaration)) = jump_point_declarations.get(name) {
+                diagnostics.push(
+                    Diagnostic::error(
+                        declaration.clone(),
+                        Error::ImportedJumpPointDeclared { name: name.clone(), line: declaration.location.clone() },
+                    )
+                        .with_related(span.clone(), "imported here")
+                );
+            }
+            imports.entry(name).or_insert(span);
+        }
+
+        for (span, name) in self.exports.iter().chain(self.entry.iter()) {
+            match jump_point_declarations.get_key_value(name) {
+                Some((name, _)) => {
+                    used.insert(name.as_str());
+                }
+                None => diagnostics.push_error(
+                    span.clone(),
+                    Error::UndefinedJumpPoint { name: name.clone(), line: span.location.clone() },
+                ),
+            }
+        }
+
+        for (addr, &mut (ref i, ref mut cl)) in self.code_lines.iter_mut().enumerate() {
+            if let CodeLine::DoubleToken(_, Spanned { value: ct @ CodeToken::JumpPoint(_), columns }, _) = cl {
+                let name = match ct {
+                    CodeToken::JumpPoint(jp) => jp.as_ref().to_owned(),
                     _ => unreachable!(),
+                };
+
+                match jump_point_declarations.get_key_value(&name) {
+                    Some((name, &(val, _))) => {
+                        used.insert(name.as_str());
+                        self.relocations.push(Relocation { address: addr as URS, symbol: None });
+                        *ct = CodeToken::Val(val as IRS);
+                    }
+                    None if imports.contains_key(name.as_str()) => {
+                        self.relocations.push(Relocation { address: addr as URS, symbol: Some(name) });
+                        *ct = CodeToken::Val(0);
+                    }
+                    None => diagnostics.push_error(
+                        Span::new(i.clone(), columns.clone()),
+                        Error::UndefinedJumpPoint { name: jump_point_name(ct), line: i.clone() },
+                    ),
                 }
-                *ct = CodeToken::Val(*val as IRS);
             }
         }
 
-        Ok(())
+        let expressions = self.code_lines
+            .iter()
+            .filter_map(|(_, cl)| match cl {
+                CodeLine::DoubleToken(_, Spanned { value: CodeToken::Expr(expr), .. }, _) => Some(expr),
+                _ => None
+            })
+            .chain(self.constants.iter().map(|c| &c.expr.value));
+        for expr in expressions {
+            used.extend(expr.jump_points());
+        }
+
+        for (name, (_, span)) in jump_point_declarations.iter() {
+            if !used.contains(name.as_str()) {
+                diagnostics.push_warning(
+                    span.clone(),
+                    Warning::UnusedJumpPoint { name: name.clone(), line: span.location.clone() },
+                );
+            }
+        }
+
+        self.jump_points = jump_point_declarations
+            .into_iter()
+            .map(|(name, (addr, _))| (name, addr))
+            .collect();
     }
 
-    fn get_jump_point_declarations(&mut self) -> HashMap<String, usize> {
-        self.code_lines
-            .iter_mut()
-            .enumerate()
-            .filter_map(|(i, (_, cl))| {
-                match cl {
-                    CodeLine::SingleToken(ct @ CodeToken::JumpPointDeclaration(_)) => {
-                        let ct = std::mem::replace(ct, CodeToken::Inst(Instruction::NOOP));
-                        match ct {
-                            CodeToken::JumpPointDeclaration(JumpPoint(jp)) => Some((jp, i)),
-                            _ => unreachable!()
+    /// Evaluates all constants, and replaces all expressions with their values.
+    fn evaluate_expressions(&mut self, diagnostics: &mut Diagnostics) {
+        // imported jump points are relocated when linking, and count as 0 till then
+        let jump_points = self.jump_points
+            .iter()
+            .map(|(name, &addr)| (name.clone(), addr as IRS))
+            .chain(self.imports.iter().map(|(_, name)| (name.clone(), 0)))
+            .collect::<HashMap<_, _>>();
+        let mut values = HashMap::new();
+        let mut failed = HashSet::new();
+
+        for i in 0..self.constants.len() {
+            self.evaluate_constant(i, &jump_points, &mut values, &mut failed, &mut Vec::new(), diagnostics);
+        }
+        for constant in self.constants.iter_mut() {
+            constant.value = values.get(&constant.name.value).copied();
+        }
+
+        let constants = &self.constants;
+        let constant = |name: &str| constants
+            .iter()
+            .find(|c| c.name.value == name)
+            .map(|c| &c.expr.value);
+        let declared = &self.jump_points;
+        for (addr, &mut (ref i, ref mut cl)) in self.code_lines.iter_mut().enumerate() {
+            if let CodeLine::DoubleToken(_, Spanned { value: ct @ CodeToken::Expr(_), columns }, _) = cl {
+                let expr = match ct {
+                    CodeToken::Expr(expr) => expr,
+                    _ => unreachable!(),
+                };
+
+                // errors in constants were already reported where they were defined
+                if expr.constants().iter().any(|&name| failed.contains(name)) {
+                    continue;
+                }
+
+                match expr.evaluate(&values, &jump_points) {
+                    Ok(val) => {
+                        match relocation(addr as URS, expr.jump_point_weights(&constant), declared) {
+                            Ok(Some(relocation)) => self.relocations.push(relocation),
+                            Ok(None) => {}
+                            Err(()) => self.unrelocatable.push((i.clone(), expr.to_string())),
                         }
+                        *ct = CodeToken::Val(val);
                     }
-                    _ => None
+                    Err(err) => diagnostics.push_error(
+                        Span::new(i.clone(), columns.clone()),
+                        expression_error(expr, i.clone(), err),
+                    ),
                 }
-            })
-            .collect::<HashMap<String, usize>>()
+            }
+        }
+    }
+
+    /// Evaluates a constant after all constants it depends on.
+    ///
+    /// `stack` contains all constants that are currently evaluated, to detect cycles.
+    fn evaluate_constant(
+        &self,
+        i: usize,
+        jump_points: &HashMap<String, IRS>,
+        values: &mut HashMap<String, IRS>,
+        failed: &mut HashSet<String>,
+        stack: &mut Vec<usize>,
+        diagnostics: &mut Diagnostics,
+    ) {
+        let constant = &self.constants[i];
+        let name = &constant.name.value;
+        if values.contains_key(name) || failed.contains(name) {
+            return;
+        }
+
+        if let Some(start) = stack.iter().position(|&j| j == i) {
+            let cycle = stack[start..]
+                .iter()
+                .chain(std::iter::once(&i))
+                .map(|&j| self.constants[j].name.value.as_str())
+                .collect::<Vec<_>>()
+                .join(" -> ");
+
+            diagnostics.push_error(
+                constant.span(),
+                Error::CyclicConstant { name: name.clone(), line: constant.line.clone(), cycle },
+            );
+            for &j in stack[start..].iter() {
+                failed.insert(self.constants[j].name.value.clone());
+            }
+            return;
+        }
+
+        stack.push(i);
+        for dependency in constant.expr.value.constants() {
+            if let Some(j) = self.constants.iter().position(|c| c.name.value == dependency) {
+                self.evaluate_constant(j, jump_points, values, failed, stack, diagnostics);
+            }
+        }
+        stack.pop();
+
+        if failed.contains(name) {
+            return;
+        }
+        if constant.expr.value.constants().iter().any(|&dependency| failed.contains(dependency)) {
+            failed.insert(name.clone());
+            return;
+        }
+
+        match constant.expr.value.evaluate(values, jump_points) {
+            Ok(val) => {
+                values.insert(name.clone(), val);
+            }
+            Err(err) => {
+                diagnostics.push_error(
+                    Span::new(constant.line.clone(), constant.expr.columns.clone()),
+                    expression_error(&constant.expr.value, constant.line.clone(), err),
+                );
+                failed.insert(name.clone());
+            }
+        }
+    }
+
+    /// Collects all jump point declarations together with the address they point to,
+    /// and replaces them with `NOOP`s.
+    fn get_jump_point_declarations(&mut self, options: &Options, diagnostics: &mut Diagnostics) -> HashMap<String, (usize, Span)> {
+        let mut declarations = HashMap::<String, (usize, Span)>::new();
+        let mut lowercase_declarations = HashMap::<String, (String, Span)>::new();
+
+        let is_declaration = self.code_lines
+            .iter()
+            .map(|(_, cl)| cl.as_jump_point_declaration().is_some())
+            .collect::<Vec<_>>();
+
+        for addr in 0..self.code_lines.len() {
+            let (i, cl) = &mut self.code_lines[addr];
+            let (ct, columns) = match cl {
+                CodeLine::SingleToken(Spanned { value: ct @ CodeToken::JumpPointDeclaration(_), columns }) => (ct, columns),
+                _ => continue
+            };
+
+            let jp = match std::mem::replace(ct, CodeToken::Inst(Instruction::NOOP)) {
+                CodeToken::JumpPointDeclaration(JumpPoint(jp)) => jp,
+                _ => unreachable!()
+            };
+            let span = Span::new(i.clone(), columns.clone());
+
+            if let Some((_, first)) = declarations.get(&jp) {
+                diagnostics.push(
+                    Diagnostic::error(
+                        span,
+                        Error::DuplicateJumpPoint {
+                            name: jp.clone(),
+                            line: i.clone(),
+                            first_line: first.location.clone(),
+                        },
+                    )
+                        .with_related(first.clone(), "first declared here")
+                );
+                continue;
+            }
+
+            if options.strict_jump_points {
+                if let Some((other, other_span)) = lowercase_declarations.get(&jp.to_lowercase()) {
+                    diagnostics.push(
+                        Diagnostic::error(
+                            span.clone(),
+                            Error::JumpPointDiffersOnlyInCase {
+                                name: jp.clone(),
+                                line: i.clone(),
+                                other: other.clone(),
+                                other_line: other_span.location.clone(),
+                            },
+                        )
+                            .with_related(other_span.clone(), format!("`.{}` is declared here", other))
+                    );
+                } else {
+                    lowercase_declarations.insert(jp.to_lowercase(), (jp.clone(), span.clone()));
+                }
+            }
+
+            let next_instruction = (addr + 1..self.code_lines.len())
+                .find(|&next| !is_declaration[next])
+                .and_then(|next| self.code_lines[next].1.instruction());
+            if let Some(Instruction::END) = next_instruction {
+                diagnostics.push_warning(
+                    span.clone(),
+                    Warning::JumpPointBeforeEnd { name: jp.clone(), line: span.location.clone() },
+                );
+            }
+
+            declarations.insert(jp, (addr, span));
+        }
+
+        declarations
+    }
+}
+
+/// The state that is shared between all files while parsing.
+struct Context<'a> {
+    provider: &'a dyn SourceProvider,
+    macros: Macros,
+    /// The files that are currently being included, to detect cycles.
+    includes: Vec<String>,
+    /// All files that were included so far.
+    included: HashSet<String>,
+}
+
+/// All macros defined so far, by their lowercase name.
+#[derive(Default)]
+struct Macros {
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+}
+
+impl Macros {
+    fn get(&self, name: &str) -> Option<&Macro> {
+        self.macros.get(&name.to_lowercase())
+    }
+
+    fn define(&mut self, mac: Macro, diagnostics: &mut Diagnostics) {
+        match self.get(&mac.name.value) {
+            Some(first) => diagnostics.push(
+                Diagnostic::error(
+                    mac.span(),
+                    Error::DuplicateMacro {
+                        name: mac.name.value.clone(),
+                        line: mac.line.clone(),
+                        first_line: first.line.clone(),
+                    },
+                )
+                    .with_related(first.span(), "first defined here")
+            ),
+            None => {
+                self.macros.insert(mac.name.value.to_lowercase(), mac);
+            }
+        }
+    }
+
+    /// A unique number for every expansion, to keep jump points local to it.
+    fn next_expansion(&mut self) -> usize {
+        self.expansions += 1;
+        self.expansions
+    }
+}
+
+fn expression_error(expr: &expression::Expression, line: Location, err: ExpressionError) -> Error {
+    match err {
+        ExpressionError::UndefinedConstant { name } => Error::UndefinedConstant { name, line },
+        ExpressionError::UndefinedJumpPoint { name } => Error::UndefinedJumpPoint { name, line },
+        err => Error::ExpressionFailed { expr: expr.to_string(), line, err },
+    }
+}
+
+fn jump_point_name(ct: &CodeToken) -> String {
+    match ct {
+        CodeToken::JumpPoint(jp) | CodeToken::JumpPointDeclaration(jp) => jp.as_ref().to_owned(),
+        ct => ct.to_string()
+    }
+}
+
+/// The relocation of an expression argument from the weights of its jump points,
+/// fails if the value does not move together with a single jump point.
+///
+/// All jump points declared in the document move together, so only the sum of their weights matters.
+fn relocation(
+    address: URS,
+    weights: Option<HashMap<String, IRS>>,
+    declared: &HashMap<String, usize>,
+) -> std::result::Result<Option<Relocation>, ()> {
+    let (local, imported) = weights
+        .ok_or(())?
+        .into_iter()
+        .partition::<Vec<_>, _>(|(name, _)| declared.contains_key(name));
+
+    match (local.iter().map(|&(_, weight)| weight).sum::<IRS>(), imported.as_slice()) {
+        (0, []) => Ok(None),
+        (1, []) => Ok(Some(Relocation { address, symbol: None })),
+        (0, [(name, 1)]) => Ok(Some(Relocation { address, symbol: Some(name.clone()) })),
+        _ => Err(()),
     }
 }
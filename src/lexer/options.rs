@@ -0,0 +1,6 @@
+/// Options that change how a [`Document`](super::Document) is assembled.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Rejects jump points that only differ in case, like `.loop` and `.LOOP`.
+    pub strict_jump_points: bool,
+}
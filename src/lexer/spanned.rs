@@ -0,0 +1,19 @@
+use std::ops::Range;
+
+/// A value together with the columns it was parsed from.
+#[derive(Clone, Debug, derive_more::Display)]
+#[display(fmt = "{}", value)]
+pub struct Spanned<T> {
+    pub value: T,
+    pub columns: Range<usize>,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(value: T, columns: Range<usize>) -> Self {
+        Self { value, columns }
+    }
+
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> Spanned<U> {
+        Spanned::new(f(self.value), self.columns)
+    }
+}
@@ -1,4 +1,5 @@
 #![allow(non_snake_case)]
+#![allow(clippy::should_implement_trait)]
 
 pub use error::Error;
 
@@ -10,7 +11,16 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 pub const DATA_REGISTERS: usize = 16;
 
 pub mod cpu;
+pub mod diagnostic;
+pub mod disassembler;
 pub mod error;
+pub mod formatter;
+pub mod image;
 pub mod instruction;
 pub mod interrupt;
 pub mod lexer;
+pub mod linker;
+pub mod listing;
+pub mod object;
+pub mod source;
+pub mod source_map;
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+
+use crate::{Error, IRS, RAM, URS};
+use crate::error::LinkError;
+use crate::object::Object;
+
+/// Combines several objects into a single [`RAM`].
+///
+/// Objects are placed in RAM in the order they were added,
+/// so the program starts with the first instruction of the first object,
+/// unless one of the objects sets an entry point.
+#[derive(Clone, Debug, Default)]
+pub struct Linker {
+    objects: Vec<(String, Object)>,
+}
+
+/// The result of linking objects.
+#[derive(Clone, Debug, Default)]
+pub struct Linked {
+    pub ram: RAM,
+    /// All exported jump points together with their final address.
+    pub symbols: BTreeMap<String, URS>,
+    /// The address the program starts at.
+    pub entry: URS,
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an object, `name` is used to refer to it in errors.
+    pub fn add(&mut self, name: impl Into<String>, object: Object) -> &mut Self {
+        self.objects.push((name.into(), object));
+        self
+    }
+
+    pub fn link(&self) -> Result<Linked, Error> {
+        let mut errors = Vec::new();
+        let mut symbols = BTreeMap::<String, (URS, &str)>::new();
+        let mut bases = Vec::with_capacity(self.objects.len());
+        let mut entry = None::<(URS, &str)>;
+
+        let mut base = 0;
+        for (name, object) in self.objects.iter() {
+            bases.push(base);
+
+            for (symbol, &addr) in object.exports.iter() {
+                match symbols.get(symbol) {
+                    Some(&(_, first)) => errors.push(LinkError::DuplicateSymbol {
+                        name: symbol.clone(),
+                        object: name.clone(),
+                        first_object: first.to_owned(),
+                    }),
+                    None => {
+                        symbols.insert(symbol.clone(), (base + addr, name));
+                    }
+                }
+            }
+
+            if let Some(addr) = object.entry {
+                match entry {
+                    Some((_, first)) => errors.push(LinkError::DuplicateEntry {
+                        object: name.clone(),
+                        first_object: first.to_owned(),
+                    }),
+                    None => entry = Some((base + addr, name)),
+                }
+            }
+
+            base += object.code.len() as URS;
+        }
+
+        let mut ram = RAM::with_capacity(base as usize);
+        for ((name, object), &base) in self.objects.iter().zip(bases.iter()) {
+            let start = ram.len();
+            ram.extend_from_slice(&object.code);
+
+            for symbol in object.imports.iter().filter(|symbol| !symbols.contains_key(*symbol)) {
+                errors.push(LinkError::UnresolvedSymbol { name: symbol.clone(), object: name.clone() });
+            }
+
+            for relocation in object.relocations.iter() {
+                let target = match relocation.symbol {
+                    Some(ref symbol) => match symbols.get(symbol) {
+                        Some(&(addr, _)) => addr,
+                        None => continue,
+                    },
+                    None => base,
+                };
+
+                let (_, arg) = &mut ram[start + relocation.address as usize];
+                *arg = arg.wrapping_add(target as IRS);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(Linked {
+                ram,
+                symbols: symbols
+                    .into_iter()
+                    .map(|(symbol, (addr, _))| (symbol, addr))
+                    .collect(),
+                entry: entry.map_or(0, |(addr, _)| addr),
+            })
+        } else {
+            Err(Error::Linking(errors))
+        }
+    }
+}
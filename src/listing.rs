@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::{IRS, URS};
+use crate::source::Location;
+
+/// A classic assembly listing: every source line next to the words it was assembled to,
+/// followed by a table of all symbols.
+///
+/// Created by [`Document::listing`](crate::lexer::Document::listing).
+#[derive(Clone, Debug, Default)]
+pub struct Listing {
+    pub rows: Vec<Row>,
+    /// All jump points together with their address, ordered by address.
+    pub jump_points: Vec<(String, URS)>,
+    /// All constants together with their value, in the order they were defined.
+    pub constants: Vec<(String, IRS)>,
+    /// Jump points that are declared in other objects.
+    pub imports: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Row {
+    /// The following lines are from another file, `None` for the main file.
+    File(Option<Arc<str>>),
+    /// A source line, and the first word it was assembled to.
+    Line { location: Location, text: String, word: Option<Word> },
+    /// A further word assembled from the line before, like the stores of `.string`.
+    Word(Word),
+}
+
+/// An assembled instruction.
+#[derive(Clone, Debug)]
+pub struct Word {
+    pub address: URS,
+    pub inst: URS,
+    pub val: IRS,
+    /// The jump point the argument was resolved from.
+    pub target: Option<String>,
+}
+
+impl Listing {
+    /// Builds the rows from the location and word of every address.
+    ///
+    /// `source` returns the source code of a file, `None` stands for the main file.
+    pub(crate) fn rows<'s, F>(words: Vec<(Location, Word)>, source: F) -> Vec<Row>
+        where F: Fn(Option<&str>) -> Option<&'s str>
+    {
+        let mut rows = Vec::new();
+        let mut files = Vec::<Option<Arc<str>>>::new();
+        let mut printed = HashMap::<Option<Arc<str>>, usize>::new();
+        let mut current = None::<Option<Arc<str>>>;
+
+        let lines = |file: &Option<Arc<str>>| source(file.as_deref())
+            .map(|s| s.lines().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let push_lines = |rows: &mut Vec<Row>, file: &Option<Arc<str>>, printed: &mut usize, until: usize| {
+            let text = lines(file);
+            for line in *printed + 1..=until {
+                rows.push(Row::Line {
+                    location: Location::new(file.clone(), line),
+                    text: text.get(line - 1).copied().unwrap_or_default().to_owned(),
+                    word: None,
+                });
+            }
+            *printed = (*printed).max(until);
+        };
+
+        for (location, word) in words {
+            let file = location.file.clone();
+
+            if current.as_ref() != Some(&file) {
+                // an included file is done once the file that included it continues
+                if let Some(previous) = current.take() {
+                    if files.contains(&file) {
+                        let until = lines(&previous).len();
+                        push_lines(&mut rows, &previous, printed.entry(previous.clone()).or_default(), until);
+                    }
+                    rows.push(Row::File(file.clone()));
+                } else if file.is_some() {
+                    rows.push(Row::File(file.clone()));
+                }
+                if !files.contains(&file) {
+                    files.push(file.clone());
+                }
+                current = Some(file.clone());
+            }
+
+            let printed = printed.entry(file.clone()).or_default();
+            if location.line > *printed {
+                push_lines(&mut rows, &file, printed, location.line - 1);
+                rows.push(Row::Line {
+                    location: location.clone(),
+                    text: lines(&file).get(location.line - 1).copied().unwrap_or_default().to_owned(),
+                    word: Some(word),
+                });
+                *printed = location.line;
+            } else {
+                rows.push(Row::Word(word));
+            }
+        }
+
+        if !files.contains(&None) {
+            files.insert(0, None);
+        }
+        for file in files {
+            let until = lines(&file).len();
+            let printed = printed.entry(file.clone()).or_default();
+            if *printed < until {
+                if current.as_ref() != Some(&file) {
+                    rows.push(Row::File(file.clone()));
+                    current = Some(file.clone());
+                }
+                push_lines(&mut rows, &file, printed, until);
+            }
+        }
+
+        rows
+    }
+}
+
+impl std::fmt::Display for Listing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let words = || self.rows.iter().filter_map(|row| match row {
+            Row::Line { word, .. } => word.as_ref(),
+            Row::Word(word) => Some(word),
+            Row::File(_) => None,
+        });
+        let code_width = words().map(|word| word.inst.to_string().len()).max().unwrap_or(0).max(4);
+        let arg_width = words().map(|word| word.val.to_string().len()).max().unwrap_or(0).max(8);
+        let target_width = words().filter_map(|word| word.target.as_ref()).map(|t| t.len() + 1).max().unwrap_or(0);
+
+        let word = |word: Option<&Word>| match word {
+            Some(word) => format!(
+                "{:>4}  {:>cw$}  {:>aw$} {:<tw$}",
+                word.address,
+                word.inst,
+                word.val,
+                word.target.as_ref().map(|t| format!(".{}", t)).unwrap_or_default(),
+                cw = code_width,
+                aw = arg_width,
+                tw = target_width,
+            ),
+            None => format!("{:4}  {:cw$}  {:aw$} {:tw$}", "", "", "", "", cw = code_width, aw = arg_width, tw = target_width),
+        };
+
+        writeln!(
+            f,
+            "{:>4}  {:>cw$}  {:>aw$} {:tw$}  {:>4}  source",
+            "addr", "code", "argument", "", "line",
+            cw = code_width, aw = arg_width, tw = target_width,
+        )?;
+        for row in self.rows.iter() {
+            let line = match row {
+                Row::File(file) => format!("{}  {:4}  ; {}", word(None), "", file.as_deref().unwrap_or("main file")),
+                Row::Line { location, text, word: w } => format!("{}  {:>4}  {}", word(w.as_ref()), location.line, text),
+                Row::Word(w) => word(Some(w)),
+            };
+            writeln!(f, "{}", line.trim_end())?;
+        }
+
+        if !self.jump_points.is_empty() {
+            writeln!(f, "\nJump points:")?;
+            let width = self.jump_points.iter().map(|(name, _)| name.len() + 1).max().unwrap_or(0);
+            for (name, addr) in self.jump_points.iter() {
+                writeln!(f, "  {:<w$}  {:>4}", format!(".{}", name), addr, w = width)?;
+            }
+        }
+        if !self.constants.is_empty() {
+            writeln!(f, "\nConstants:")?;
+            let width = self.constants.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+            for (name, val) in self.constants.iter() {
+                writeln!(f, "  {:<w$}  {:>4}", name, val, w = width)?;
+            }
+        }
+        if !self.imports.is_empty() {
+            writeln!(f, "\nImports:")?;
+            for name in self.imports.iter() {
+                writeln!(f, "  .{}", name)?;
+            }
+        }
+
+        Ok(())
+    }
+}
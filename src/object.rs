@@ -0,0 +1,142 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+use crate::{Error, IRS, RAM, URS};
+
+/// A separately assembled module, that still has to be linked into a [`RAM`].
+///
+/// All addresses are relative to the start of the object.
+/// Objects are stored as text:
+///
+/// ```text
+/// kasm-object 1
+/// code 3
+/// 16 0
+/// 7 0
+/// 14 0
+/// export main 0
+/// import print
+/// reloc 1 print
+/// entry 0
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Object {
+    pub code: RAM,
+    /// Jump points other objects may use, together with their address.
+    pub exports: BTreeMap<String, URS>,
+    /// Jump points that are declared in other objects.
+    pub imports: BTreeSet<String>,
+    pub relocations: Vec<Relocation>,
+    /// Where the program starts, if the object sets it.
+    pub entry: Option<URS>,
+}
+
+/// An argument in the code, that depends on where objects end up in RAM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Relocation {
+    /// The address of the instruction whose argument is relocated.
+    pub address: URS,
+    /// The imported jump point the argument points to,
+    /// or `None` if it points into the same object.
+    ///
+    /// The final address is added to the current argument.
+    pub symbol: Option<String>,
+}
+
+impl Object {
+    pub const MAGIC: &'static str = "kasm-object";
+    pub const VERSION: u32 = 1;
+}
+
+impl std::fmt::Display for Object {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} {}", Self::MAGIC, Self::VERSION)?;
+        writeln!(f, "code {}", self.code.len())?;
+        for (inst, val) in self.code.iter() {
+            writeln!(f, "{} {}", inst, val)?;
+        }
+        for (name, addr) in self.exports.iter() {
+            writeln!(f, "export {} {}", name, addr)?;
+        }
+        for name in self.imports.iter() {
+            writeln!(f, "import {}", name)?;
+        }
+        for relocation in self.relocations.iter() {
+            match relocation.symbol {
+                Some(ref symbol) => writeln!(f, "reloc {} {}", relocation.address, symbol)?,
+                None => writeln!(f, "reloc {}", relocation.address)?,
+            }
+        }
+        if let Some(entry) = self.entry {
+            writeln!(f, "entry {}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Object {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().enumerate().map(|(i, line)| (i + 1, line.split_ascii_whitespace().collect::<Vec<_>>()));
+        let invalid = |line: usize, reason: &str| Error::InvalidObject { line, reason: reason.to_owned() };
+
+        match lines.next() {
+            Some((_, ref header)) if header.len() == 2 && header[0] == Self::MAGIC => {
+                if header[1] != Self::VERSION.to_string() {
+                    return Err(invalid(1, "the version of the object format is not supported"));
+                }
+            }
+            _ => return Err(invalid(1, "the file is not a kasm object")),
+        }
+
+        let len = match lines.next() {
+            Some((_, ref parts)) if parts.len() == 2 && parts[0] == "code" => parts[1]
+                .parse::<usize>()
+                .map_err(|_| invalid(2, "the length of the code is not a number"))?,
+            _ => return Err(invalid(2, "expected `code <length>`")),
+        };
+
+        let mut object = Self::default();
+        for _ in 0..len {
+            let (i, parts) = lines
+                .next()
+                .ok_or_else(|| invalid(len + 2, "the code is shorter than its length"))?;
+            match parts.as_slice() {
+                [inst, val] => object.code.push((
+                    inst.parse::<URS>().map_err(|_| invalid(i, "the instruction is not a number"))?,
+                    val.parse::<IRS>().map_err(|_| invalid(i, "the argument is not a number"))?,
+                )),
+                _ => return Err(invalid(i, "expected `<instruction> <argument>`")),
+            }
+        }
+
+        for (i, parts) in lines {
+            let address = |s: &str| s
+                .parse::<URS>()
+                .ok()
+                .filter(|&addr| addr < len as URS)
+                .ok_or_else(|| invalid(i, "the address is not inside of the code"));
+
+            match parts.as_slice() {
+                [] => {}
+                ["export", name, addr] => {
+                    object.exports.insert(name.to_string(), address(addr)?);
+                }
+                ["import", name] => {
+                    object.imports.insert(name.to_string());
+                }
+                ["reloc", addr] => object.relocations.push(Relocation { address: address(addr)?, symbol: None }),
+                ["reloc", addr, symbol] if object.imports.contains(*symbol) => object.relocations.push(Relocation {
+                    address: address(addr)?,
+                    symbol: Some(symbol.to_string()),
+                }),
+                ["reloc", _, _] => return Err(invalid(i, "the relocated symbol is not imported")),
+                ["entry", addr] => object.entry = Some(address(addr)?),
+                _ => return Err(invalid(i, "expected `export`, `import`, `reloc` or `entry`")),
+            }
+        }
+
+        Ok(object)
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// A line in one of the files of a program.
+///
+/// Lines start at 1. The main file has no name, all included files are named
+/// by the path they were resolved to.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Location {
+    pub file: Option<Arc<str>>,
+    pub line: usize,
+}
+
+impl Location {
+    pub fn new(file: Option<Arc<str>>, line: usize) -> Self {
+        Self { file, line }
+    }
+
+    /// A line in the main file.
+    pub fn line(line: usize) -> Self {
+        Self { file: None, line }
+    }
+
+    pub fn is_main(&self) -> bool {
+        self.file.is_none()
+    }
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.file {
+            Some(ref file) => write!(f, "{} of {}", self.line, file),
+            None => write!(f, "{}", self.line),
+        }
+    }
+}
+
+/// Loads the files that are included with `.include "<path>"`.
+pub trait SourceProvider {
+    /// Resolves `path` relative to the file that includes it,
+    /// or relative to the main file if `from` is `None`.
+    ///
+    /// The resolved path is used to name the file in errors and to detect include cycles.
+    fn resolve(&self, path: &str, from: Option<&str>) -> String;
+
+    /// Loads the content of a resolved path.
+    fn load(&self, path: &str) -> std::io::Result<String>;
+}
+
+/// Loads included files from the file system.
+#[derive(Clone, Debug, Default)]
+pub struct FileSystem {
+    root: PathBuf,
+}
+
+impl FileSystem {
+    /// `root` is the directory paths in the main file are relative to.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl SourceProvider for FileSystem {
+    fn resolve(&self, path: &str, from: Option<&str>) -> String {
+        let dir = match from {
+            Some(from) => Path::new(from).parent().unwrap_or_else(|| Path::new("")),
+            None => &self.root,
+        };
+
+        normalize(&dir.join(path)).to_string_lossy().into_owned()
+    }
+
+    fn load(&self, path: &str) -> std::io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+}
+
+/// Loads included files from a map of paths to their content, for example
+/// for files that only exist in the browser.
+///
+/// Paths are used as they were written, without resolving them.
+#[derive(Clone, Debug, Default)]
+pub struct InMemory {
+    files: HashMap<String, String>,
+}
+
+impl InMemory {
+    pub fn new(files: HashMap<String, String>) -> Self {
+        Self { files }
+    }
+
+    pub fn insert(&mut self, path: impl Into<String>, source: impl Into<String>) {
+        self.files.insert(path.into(), source.into());
+    }
+}
+
+impl SourceProvider for InMemory {
+    fn resolve(&self, path: &str, _: Option<&str>) -> String {
+        path.trim_start_matches("./").to_owned()
+    }
+
+    fn load(&self, path: &str) -> std::io::Result<String> {
+        self.files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "No such file"))
+    }
+}
+
+/// Removes `.` and resolves `..` components, without touching the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
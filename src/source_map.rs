@@ -0,0 +1,65 @@
+use std::collections::BTreeMap;
+
+use crate::{Error, URS};
+use crate::source::Location;
+
+/// Maps RAM addresses back to the source lines they were assembled from.
+#[derive(Clone, Debug, Default)]
+pub struct SourceMap {
+    lines: Vec<Location>,
+    labels: BTreeMap<URS, Vec<String>>,
+}
+
+impl SourceMap {
+    pub fn new(lines: Vec<Location>, labels: BTreeMap<URS, Vec<String>>) -> Self {
+        Self { lines, labels }
+    }
+
+    /// The source line the instruction at `addr` was assembled from.
+    pub fn line(&self, addr: URS) -> Option<&Location> {
+        self.lines.get(addr as usize)
+    }
+
+    /// The first address assembled from `line`.
+    pub fn address(&self, line: &Location) -> Option<URS> {
+        self.lines
+            .iter()
+            .position(|l| l == line)
+            .map(|addr| addr as URS)
+    }
+
+    /// All labels that point to `addr`.
+    pub fn labels(&self, addr: URS) -> &[String] {
+        self.labels
+            .get(&addr)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// All labels together with the address they point to, ordered by address.
+    pub fn all_labels(&self) -> impl Iterator<Item=(URS, &str)> {
+        self.labels
+            .iter()
+            .flat_map(|(&addr, names)| names.iter().map(move |name| (addr, name.as_str())))
+    }
+
+    pub fn lines(&self) -> &[Location] {
+        &self.lines
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Attaches the source line of the faulting instruction to a runtime error.
+    pub fn annotate(&self, err: Error) -> Error {
+        match err.BZ().and_then(|BZ| self.line(BZ).map(|line| (BZ, line.clone()))) {
+            Some((BZ, line)) if !matches!(err, Error::InLine { .. }) => Error::InLine {
+                line,
+                BZ,
+                err: Box::new(err),
+            },
+            _ => err
+        }
+    }
+}